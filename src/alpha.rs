@@ -0,0 +1,288 @@
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::png::Png;
+use crate::stego::{bytes_to_bits, KeyRng, PayloadReader, PAYLOAD_OVERHEAD};
+use anyhow::{bail, Result};
+
+/*
+利用透明度隐藏信息：
+- 带 alpha 的图像中 alpha 为 0 的像素，颜色值不影响显示，颜色通道的每个字节都可以任意改写
+- 可选：接近不透明（alpha 为最大值或最大值减 1）的像素，使用 alpha 的最低位
+- 索引色图像根据 tRNS 判断透明，在多个完全透明的调色板项之间选择即可携带比特
+灰度和真彩色图像的 tRNS 用一个精确的颜色值表示透明，改写这些像素会让它们变得可见，所以不能使用
+ */
+enum Slot {
+    // 可以任意改写的字节
+    Byte(usize),
+    // 只能改写最低位的字节
+    Lsb(usize),
+    // 索引色图像中的像素
+    Index(usize),
+}
+
+struct Carrier {
+    slots: Vec<Slot>,
+    // 完全透明的调色板项
+    transparent: Vec<u8>,
+    // 每个 Index 槽携带的比特数
+    index_bits: usize,
+}
+
+impl Carrier {
+    fn new(png: &Png, image: &Image, key: &str, near_opaque: bool) -> Result<Carrier> {
+        let ihdr = image.ihdr;
+        let pixels = ihdr.width as usize * ihdr.height as usize;
+        let mut carrier = Self {
+            slots: Vec::new(),
+            transparent: Vec::new(),
+            index_bits: 0,
+        };
+
+        match ihdr.color_type {
+            4 | 6 => {
+                let sample_bytes = ihdr.bit_depth as usize / 8;
+                let pixel_bytes = ihdr.channels() * sample_bytes;
+                for pixel in 0..pixels {
+                    let start = pixel * pixel_bytes;
+                    let alpha =
+                        &image.data[start + pixel_bytes - sample_bytes..start + pixel_bytes];
+                    if alpha.iter().all(|&x| x == 0) {
+                        carrier
+                            .slots
+                            .extend((start..start + pixel_bytes - sample_bytes).map(Slot::Byte));
+                    } else if near_opaque
+                        && alpha[..sample_bytes - 1].iter().all(|&x| x == 0xff)
+                        && alpha[sample_bytes - 1] >= 0xfe
+                    {
+                        carrier.slots.push(Slot::Lsb(start + pixel_bytes - 1));
+                    }
+                }
+            }
+            3 => {
+                if let Some(trns) = png.chunk_by_type("tRNS") {
+                    carrier.transparent = (0..trns.data().len())
+                        .filter(|&i| trns.data()[i] == 0)
+                        .map(|i| i as u8)
+                        .collect();
+                }
+                if carrier.transparent.len() >= 2 {
+                    carrier.index_bits = carrier.transparent.len().ilog2() as usize;
+                    carrier.slots.extend(
+                        (0..pixels)
+                            .filter(|&pixel| carrier.transparent.contains(&image.index(pixel)))
+                            .map(Slot::Index),
+                    );
+                }
+            }
+            _ => {}
+        }
+        KeyRng::new(key).shuffle(&mut carrier.slots);
+        Ok(carrier)
+    }
+
+    fn slot_bits(&self, slot: &Slot) -> usize {
+        match slot {
+            Slot::Byte(_) => 8,
+            Slot::Lsb(_) => 1,
+            Slot::Index(_) => self.index_bits,
+        }
+    }
+
+    fn capacity_bits(&self) -> usize {
+        self.slots.iter().map(|x| self.slot_bits(x)).sum()
+    }
+
+    fn read(&self, image: &Image, slot: &Slot) -> Vec<u8> {
+        let value = match slot {
+            Slot::Byte(pos) => image.data[*pos],
+            Slot::Lsb(pos) => image.data[*pos] & 1,
+            Slot::Index(pixel) => {
+                let index = image.index(*pixel);
+                self.transparent
+                    .iter()
+                    .position(|&x| x == index)
+                    .unwrap_or(0) as u8
+            }
+        };
+        let bits = self.slot_bits(slot);
+        (0..bits).rev().map(|i| (value >> i) & 1).collect()
+    }
+
+    // 写入比特，返回值是否发生变化
+    fn write(&self, image: &mut Image, slot: &Slot, bits: &[u8]) -> bool {
+        let value = bits.iter().fold(0, |acc, &bit| (acc << 1) | bit)
+            << (self.slot_bits(slot) - bits.len());
+        match slot {
+            Slot::Byte(pos) => {
+                let changed = image.data[*pos] != value;
+                image.data[*pos] = value;
+                changed
+            }
+            Slot::Lsb(pos) => {
+                let changed = image.data[*pos] & 1 != value;
+                image.data[*pos] = (image.data[*pos] & !1) | value;
+                changed
+            }
+            Slot::Index(pixel) => {
+                let index = self.transparent[value as usize];
+                let changed = image.index(*pixel) != index;
+                image.set_index(*pixel, index);
+                changed
+            }
+        }
+    }
+}
+
+// 可嵌入的消息字节数（已扣除长度、块类型和 CRC）
+pub(crate) fn capacity(png: &Png, image: &Image, key: &str, near_opaque: bool) -> Result<usize> {
+    let carrier = Carrier::new(png, image, key, near_opaque)?;
+    Ok((carrier.capacity_bits() / 8).saturating_sub(PAYLOAD_OVERHEAD))
+}
+
+// 嵌入消息，返回被修改的样本数
+pub(crate) fn embed(
+    png: &Png,
+    image: &mut Image,
+    key: &str,
+    near_opaque: bool,
+    chunk: &Chunk,
+) -> Result<usize> {
+    let carrier = Carrier::new(png, image, key, near_opaque)?;
+    let bits = bytes_to_bits(&chunk.as_bytes());
+    let capacity_bits = carrier.capacity_bits();
+    if bits.len() > capacity_bits {
+        bail!(
+            "Message too large: needs {} bytes, only {} available",
+            chunk.data().len(),
+            (capacity_bits / 8).saturating_sub(PAYLOAD_OVERHEAD)
+        );
+    }
+
+    let mut changed = 0;
+    let mut rest = &bits[..];
+    for slot in &carrier.slots {
+        if rest.is_empty() {
+            break;
+        }
+        let (slot_bits, remain) = rest.split_at(carrier.slot_bits(slot).min(rest.len()));
+        if carrier.write(image, slot, slot_bits) {
+            changed += 1;
+        }
+        rest = remain;
+    }
+    Ok(changed)
+}
+
+pub(crate) fn extract(png: &Png, image: &Image, key: &str, near_opaque: bool) -> Result<Chunk> {
+    let carrier = Carrier::new(png, image, key, near_opaque)?;
+    let mut reader = PayloadReader::default();
+    for slot in &carrier.slots {
+        if let Some(chunk) = reader.push(carrier.read(image, slot))? {
+            return Ok(chunk);
+        }
+    }
+    bail!("No embedded message found (wrong key?)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    // 左半边完全透明，右半边完全不透明
+    fn half_transparent_image() -> Image {
+        let mut image = testing_image(16, 16, 6, 8);
+        for pixel in 0..16 * 16 {
+            image.data[pixel * 4 + 3] = if pixel % 16 < 8 { 0 } else { 255 };
+        }
+        image
+    }
+
+    fn testing_chunk(message: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_alpha_round_trip() {
+        let mut image = half_transparent_image();
+        let png = image.to_png().unwrap();
+        let original = image.clone();
+        embed(&png, &mut image, "key", false, &testing_chunk("hidden")).unwrap();
+
+        let actual = extract(&png, &image, "key", false).unwrap();
+        assert_eq!(actual.data_as_string().unwrap(), "hidden");
+        // 可见的像素保持不变
+        for pixel in (0..16 * 16).filter(|pixel| pixel % 16 >= 8) {
+            assert_eq!(
+                image.data[pixel * 4..pixel * 4 + 4],
+                original.data[pixel * 4..pixel * 4 + 4]
+            );
+        }
+    }
+
+    #[test]
+    fn test_alpha_capacity() {
+        let image = half_transparent_image();
+        let png = image.to_png().unwrap();
+        assert_eq!(
+            capacity(&png, &image, "key", false).unwrap(),
+            128 * 3 - PAYLOAD_OVERHEAD
+        );
+        assert_eq!(
+            capacity(&png, &image, "key", true).unwrap(),
+            128 * 3 + 16 - 12
+        );
+
+        let mut image = half_transparent_image();
+        let message = "x".repeat(128 * 3 - 11);
+        assert!(embed(&png, &mut image, "key", false, &testing_chunk(&message)).is_err());
+    }
+
+    #[test]
+    fn test_alpha_near_opaque() {
+        let mut image = testing_image(16, 16, 6, 8);
+        for pixel in 0..16 * 16 {
+            image.data[pixel * 4 + 3] = 255;
+        }
+        let png = image.to_png().unwrap();
+        embed(&png, &mut image, "key", true, &testing_chunk("lsb")).unwrap();
+        assert!((0..16 * 16).all(|pixel| image.data[pixel * 4 + 3] >= 254));
+        let actual = extract(&png, &image, "key", true).unwrap();
+        assert_eq!(actual.data_as_string().unwrap(), "lsb");
+    }
+
+    #[test]
+    fn test_alpha_palette_trns() {
+        let mut image = testing_image(32, 32, 3, 4);
+        let mut png = image.to_png().unwrap();
+        // 调色板项 0 到 3 完全透明
+        let trns = Chunk::new(
+            ChunkType::from_str("tRNS").unwrap(),
+            vec![0, 0, 0, 0, 255, 255],
+        );
+        png.append_chunk(trns);
+        assert!(capacity(&png, &image, "key", false).unwrap() > 0);
+
+        let original = image.clone();
+        embed(&png, &mut image, "key", false, &testing_chunk("trns")).unwrap();
+        let actual = extract(&png, &image, "key", false).unwrap();
+        assert_eq!(actual.data_as_string().unwrap(), "trns");
+        for pixel in 0..32 * 32 {
+            assert_eq!(original.index(pixel) < 4, image.index(pixel) < 4);
+        }
+    }
+
+    #[test]
+    fn test_alpha_truecolor_trns_has_no_capacity() {
+        let image = testing_image(16, 16, 2, 8);
+        let mut png = image.to_png().unwrap();
+        let trns = Chunk::new(ChunkType::from_str("tRNS").unwrap(), vec![0, 0, 0, 0, 0, 0]);
+        png.append_chunk(trns);
+        assert_eq!(capacity(&png, &image, "key", true).unwrap(), 0);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::image::Planes;
+use crate::stats::{chi_square_tail, smaller_root};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/*
+经典的 LSB 隐写分析，对每个颜色通道分别进行：
+- 卡方攻击（Westfeld-Pfitzmann）：LSB 替换会让值 2k 和 2k+1 的出现次数趋于相等，
+  p 值接近 1 表示存在隐写；按扫描顺序对逐渐增长的前缀计算，可以估计顺序嵌入的长度
+- RS 分析（Fridrich）：比较翻转最低位前后平滑度增加（R）和降低（S）的像素组的比例
+- 样本对分析（Dumitrescu-Wu-Wang）：统计相邻样本对的结构，解二次方程得到嵌入率
+嵌入率表示被随机改写最低位的样本占全部样本的比例。
+ */
+const GROUP: usize = 4;
+const MASK: [bool; GROUP] = [false, true, true, false];
+const PREFIX_STEPS: usize = 100;
+
+#[derive(Serialize)]
+pub(crate) struct ChannelAnalysis {
+    pub(crate) channel: &'static str,
+    pub(crate) chi_square_p: f64,
+    pub(crate) chi_square_rate: f64,
+    pub(crate) rs_rate: f64,
+    pub(crate) spa_rate: f64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Analysis {
+    pub(crate) channels: Vec<ChannelAnalysis>,
+}
+
+// 卡方攻击的 p 值：样本值对的频数越接近，p 越大
+fn chi_square_p(samples: &[u16]) -> f64 {
+    let mut histogram = [0usize; 256];
+    for &x in samples {
+        histogram[(x & 0xff) as usize] += 1;
+    }
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // 期望频数太小时卡方近似不可靠
+        if expected < 5.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    chi_square_tail(statistic, (categories - 1) as f64)
+}
+
+// 从开头起 p 值持续大于 0.5 的前缀所占的比例
+fn chi_square_rate(samples: &[u16]) -> f64 {
+    let steps = (1..=PREFIX_STEPS)
+        .take_while(|&step| chi_square_p(&samples[..samples.len() * step / PREFIX_STEPS]) > 0.5)
+        .count();
+    steps as f64 / PREFIX_STEPS as f64
+}
+
+fn smoothness(group: &[i32]) -> i32 {
+    group.windows(2).map(|x| (x[1] - x[0]).abs()).sum()
+}
+
+// F1 翻转最低位（0 <-> 1），F-1 为 -1 <-> 0、1 <-> 2
+fn flip(value: i32, negative: bool) -> i32 {
+    if negative {
+        ((value + 1) ^ 1) - 1
+    } else {
+        value ^ 1
+    }
+}
+
+// 返回使用掩码 M 和 -M 时 R 组与 S 组比例之差
+fn rs_differences(rows: &[Vec<i32>]) -> (f64, f64) {
+    let mut counts = [0i64; 2];
+    let mut groups = 0;
+    for row in rows {
+        for group in row.chunks_exact(GROUP) {
+            let original = smoothness(group);
+            for (i, negative) in [false, true].into_iter().enumerate() {
+                let flipped: Vec<i32> = group
+                    .iter()
+                    .zip(MASK)
+                    .map(|(&x, masked)| if masked { flip(x, negative) } else { x })
+                    .collect();
+                counts[i] += match smoothness(&flipped).cmp(&original) {
+                    std::cmp::Ordering::Greater => 1,
+                    std::cmp::Ordering::Less => -1,
+                    std::cmp::Ordering::Equal => 0,
+                };
+            }
+            groups += 1;
+        }
+    }
+    let groups = groups.max(1) as f64;
+    (counts[0] as f64 / groups, counts[1] as f64 / groups)
+}
+
+fn rs_rate(planes: &Planes, samples: &[u16]) -> f64 {
+    let rows: Vec<Vec<i32>> = samples
+        .chunks(planes.width)
+        .map(|row| row.iter().map(|&x| x as i32).collect())
+        .collect();
+    let flipped: Vec<Vec<i32>> = rows
+        .iter()
+        .map(|row| row.iter().map(|&x| x ^ 1).collect())
+        .collect();
+    let (d0, dn0) = rs_differences(&rows);
+    let (d1, dn1) = rs_differences(&flipped);
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+    smaller_root(a, b, c)
+        .map(|x| (x / (x - 0.5)).clamp(0.0, 1.0))
+        .unwrap_or(0.0)
+}
+
+fn spa_rate(planes: &Planes, samples: &[u16]) -> f64 {
+    let (mut x, mut y, mut z, mut w, mut total) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for row in samples.chunks(planes.width) {
+        for pair in row.windows(2) {
+            let (u, v) = (pair[0], pair[1]);
+            let even = v % 2 == 0;
+            if (even && u < v) || (!even && u > v) {
+                x += 1.0;
+            }
+            if (even && u > v) || (!even && u < v) {
+                y += 1.0;
+            }
+            if u == v {
+                z += 1.0;
+            }
+            if u != v && u / 2 == v / 2 {
+                w += 1.0;
+            }
+            total += 1.0;
+        }
+    }
+    smaller_root((w + z) / 2.0, 2.0 * x - total, y - x)
+        .map(|p| p.clamp(0.0, 1.0))
+        .unwrap_or(0.0)
+}
+
+impl Analysis {
+    // 只分析颜色通道，alpha 通道通常不用于隐写
+    pub(crate) fn new(planes: &Planes) -> Analysis {
+        let channels = planes
+            .names
+            .iter()
+            .zip(&planes.planes)
+            .filter(|(&name, _)| name != "A")
+            .map(|(&channel, samples)| ChannelAnalysis {
+                channel,
+                chi_square_p: chi_square_p(samples),
+                chi_square_rate: chi_square_rate(samples),
+                rs_rate: rs_rate(planes, samples),
+                spa_rate: spa_rate(planes, samples),
+            })
+            .collect();
+        Self { channels }
+    }
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<8} {:>12} {:>12} {:>8} {:>8}",
+            "Channel", "Chi-square p", "Chi-sq rate", "RS", "SPA"
+        )?;
+        for channel in &self.channels {
+            write!(
+                f,
+                "\n{:<8} {:>12.4} {:>12.2} {:>8.3} {:>8.3}",
+                channel.channel,
+                channel.chi_square_p,
+                channel.chi_square_rate,
+                channel.rs_rate,
+                channel.spa_rate
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use crate::stego::KeyRng;
+
+    /*
+    平滑变化的灰度图像，带有少量噪声。
+    combed 为 true 时模拟对比度拉伸后的梳状直方图（相邻值对的频数差别很大），
+    卡方攻击只对这类图像有效；RS 和样本对分析则假设原始最低位没有这种结构。
+     */
+    fn natural_image(combed: bool) -> Image {
+        let mut image = testing_image(256, 256, 0, 8);
+        let mut rng = KeyRng::new("texture");
+        for pixel in 0..256 * 256 {
+            let (x, y) = ((pixel % 256) as f64, (pixel / 256) as f64);
+            let smooth = (x / 23.0).sin() * (y / 31.0).cos();
+            image.data[pixel] = if combed {
+                (64.0 + 30.0 * smooth) as u8 * 2
+                    + rng.below(3) as u8 * 2
+                    + (rng.below(4) == 0) as u8
+            } else {
+                (128.0 + 60.0 * smooth) as u8 + rng.below(7) as u8
+            };
+        }
+        image
+    }
+
+    // 用随机比特替换前 rate 比例样本的最低位
+    fn embed_lsb(image: &mut Image, rate: f64) {
+        let mut rng = KeyRng::new("message");
+        let count = (image.data.len() as f64 * rate) as usize;
+        for value in &mut image.data[..count] {
+            *value = (*value & !1) | (rng.next_u64() & 1) as u8;
+        }
+    }
+
+    fn analyze_embedded(combed: bool, rate: f64) -> ChannelAnalysis {
+        let mut image = natural_image(combed);
+        embed_lsb(&mut image, rate);
+        let planes = Planes::new(&image, None).unwrap();
+        Analysis::new(&planes).channels.remove(0)
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let result = analyze_embedded(true, 0.0);
+        assert!(result.chi_square_p < 0.5);
+        assert_eq!(result.chi_square_rate, 0.0);
+
+        let result = analyze_embedded(false, 0.0);
+        assert!(result.rs_rate < 0.1);
+        assert!(result.spa_rate < 0.1);
+    }
+
+    #[test]
+    fn test_fully_embedded_image() {
+        let result = analyze_embedded(true, 1.0);
+        assert!(result.chi_square_p > 0.9);
+        assert_eq!(result.chi_square_rate, 1.0);
+
+        // 随机替换时约一半的最低位被改变，嵌入率按被改写的样本计算
+        let result = analyze_embedded(false, 1.0);
+        assert!(result.rs_rate > 0.7);
+        assert!(result.spa_rate > 0.7);
+    }
+
+    #[test]
+    fn test_partially_embedded_image() {
+        let result = analyze_embedded(true, 0.5);
+        assert!((result.chi_square_rate - 0.5).abs() < 0.1);
+
+        let result = analyze_embedded(false, 0.5);
+        assert!((result.rs_rate - 0.5).abs() < 0.15);
+        assert!((result.spa_rate - 0.5).abs() < 0.15);
+    }
+
+    #[test]
+    fn test_json_output() {
+        let planes = Planes::new(&testing_image(16, 16, 6, 8), None).unwrap();
+        let json = serde_json::to_value(Analysis::new(&planes)).unwrap();
+        let channels = json["channels"].as_array().unwrap();
+        assert_eq!(channels.len(), 3);
+        assert_eq!(channels[0]["channel"], "R");
+        assert!(channels[0]["rs_rate"].is_number());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::png::Png;
+use crate::stats::entropy;
+use std::fmt::{Display, Formatter};
+
+/*
+块层面的可疑特征，隐藏的数据经常放在这里而不是像素中：
+- 未知的私有块、未注册的公共块、保留位不正确的块
+- 过大的辅助块、熵很高的未压缩辅助块（加密或压缩过的数据）
+- 只能出现一次的块重复出现
+- IEND 之后的数据
+- 不寻常的 IDAT 分段：IDAT 不连续、空 IDAT、除最后一块外长度不一致
+每种特征有一个分数，文件的总分为所有特征分数之和（最高 100）。
+ */
+const OVERSIZED: usize = 64 * 1024;
+// ICC 配置文件和 Exif 本来就可能比较大
+const OVERSIZED_PROFILE: usize = 1024 * 1024;
+const ENTROPY_MIN_LEN: usize = 256;
+const HIGH_ENTROPY: f64 = 7.5;
+
+// 最多只能出现一次的块
+const SINGLETONS: [&[u8; 4]; 19] = [
+    b"IHDR", b"PLTE", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP",
+    b"mDCV", b"cLLI", b"bKGD", b"hIST", b"pHYs", b"eXIf", b"tIME", b"acTL", b"sCAL",
+];
+
+// 数据本身是压缩过的块，熵高是正常的
+const COMPRESSED: [&[u8; 4]; 5] = [b"IDAT", b"fdAT", b"zTXt", b"iTXt", b"iCCP"];
+
+pub(crate) struct Anomaly {
+    // 相关块的序号，与整个文件有关时为 None
+    pub(crate) chunk: Option<usize>,
+    pub(crate) description: String,
+    pub(crate) score: u32,
+}
+
+pub(crate) struct Report {
+    pub(crate) anomalies: Vec<Anomaly>,
+}
+
+impl Report {
+    pub(crate) fn score(&self) -> u32 {
+        self.anomalies.iter().map(|x| x.score).sum::<u32>().min(100)
+    }
+
+    fn push(&mut self, chunk: Option<usize>, score: u32, description: String) {
+        self.anomalies.push(Anomaly {
+            chunk,
+            description,
+            score,
+        });
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Score: {}", self.score())?;
+        for anomaly in &self.anomalies {
+            match anomaly.chunk {
+                Some(index) => write!(f, "\n  [+{:>2}] chunk #{}: ", anomaly.score, index)?,
+                None => write!(f, "\n  [+{:>2}] ", anomaly.score)?,
+            }
+            f.write_str(&anomaly.description)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn inspect(png: &Png) -> Report {
+    let mut report = Report {
+        anomalies: Vec::new(),
+    };
+    let chunks = png.chunks();
+    let mut seen: Vec<[u8; 4]> = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let bytes = chunk_type.bytes();
+        let len = chunk.data().len();
+        if !chunk_type.is_reserved_bit_valid() {
+            report.push(
+                Some(index),
+                20,
+                format!("{} has the reserved bit set", chunk_type),
+            );
+        }
+        if !chunk_type.is_public() {
+            report.push(
+                Some(index),
+                20,
+                format!("unknown private chunk {} ({} bytes)", chunk_type, len),
+            );
+        } else if !chunk_type.is_registered() {
+            report.push(
+                Some(index),
+                15,
+                format!("unregistered public chunk {} ({} bytes)", chunk_type, len),
+            );
+        }
+
+        if !chunk_type.is_critical() {
+            let limit = match &bytes {
+                b"iCCP" | b"eXIf" => OVERSIZED_PROFILE,
+                _ => OVERSIZED,
+            };
+            if len > limit {
+                report.push(
+                    Some(index),
+                    15,
+                    format!("oversized ancillary chunk {} ({} bytes)", chunk_type, len),
+                );
+            }
+            let bits = entropy(chunk.data());
+            if len >= ENTROPY_MIN_LEN && !COMPRESSED.contains(&&bytes) && bits > HIGH_ENTROPY {
+                report.push(
+                    Some(index),
+                    25,
+                    format!(
+                        "high-entropy data in {} ({:.2} bits per byte)",
+                        chunk_type, bits
+                    ),
+                );
+            }
+        }
+
+        if SINGLETONS.contains(&&bytes) && seen.contains(&bytes) {
+            report.push(Some(index), 20, format!("duplicate {} chunk", chunk_type));
+        }
+        seen.push(bytes);
+    }
+
+    inspect_idat(png, &mut report);
+    if !png.trailer().is_empty() {
+        report.push(
+            None,
+            30,
+            format!("{} bytes after IEND", png.trailer().len()),
+        );
+    }
+    report
+}
+
+/*
+编码器通常把数据流按固定长度切分，最后一块可以短一些。
+其他分段方式（例如 idat-split 方法用长度携带消息）会被标记出来。
+ */
+fn inspect_idat(png: &Png, report: &mut Report) {
+    let positions: Vec<usize> = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.chunk_type().bytes() == Png::IDAT)
+        .map(|(i, _)| i)
+        .collect();
+    let Some((&first, &last)) = positions.first().zip(positions.last()) else {
+        return;
+    };
+    if last - first + 1 != positions.len() {
+        report.push(
+            Some(last),
+            20,
+            "IDAT chunks are not consecutive".to_string(),
+        );
+    }
+    let lengths: Vec<usize> = positions
+        .iter()
+        .map(|&i| png.chunks()[i].data().len())
+        .collect();
+    let empty = lengths.iter().filter(|&&x| x == 0).count();
+    if empty > 0 {
+        report.push(None, 10, format!("{} empty IDAT chunks", empty));
+    }
+    let (&tail, body) = lengths.split_last().unwrap_or((&0, &[]));
+    let irregular =
+        body.windows(2).any(|x| x[0] != x[1]) || body.first().is_some_and(|&x| tail > x);
+    if irregular {
+        report.push(
+            None,
+            20,
+            format!(
+                "unusual IDAT split: {} chunks with lengths {:?}",
+                lengths.len(),
+                lengths
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::stego::KeyRng;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    #[test]
+    fn test_clean_png() {
+        let mut png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        png.append_chunk(chunk("tEXt", b"Comment\0plain text".to_vec()));
+        let report = inspect(&png);
+        assert!(report.anomalies.is_empty());
+        assert_eq!(report.score(), 0);
+    }
+
+    #[test]
+    fn test_chunk_anomalies() {
+        let mut png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        let mut rng = KeyRng::new("random");
+        let random: Vec<u8> = (0..4096).map(|_| rng.next_u64() as u8).collect();
+        png.append_chunk(chunk("ruSt", random));
+        png.append_chunk(chunk("gAMA", vec![0, 0, 0xb1, 0x8f]));
+        png.append_chunk(chunk("gAMA", vec![0, 0, 0xb1, 0x8f]));
+        png.append_chunk(chunk("tEXt", vec![b'a'; OVERSIZED + 1]));
+        png.set_trailer(b"after the end".to_vec());
+        let report = inspect(&png);
+        let descriptions: Vec<&str> = report
+            .anomalies
+            .iter()
+            .map(|x| x.description.as_str())
+            .collect();
+        assert_eq!(
+            descriptions,
+            [
+                "unknown private chunk ruSt (4096 bytes)",
+                "high-entropy data in ruSt (7.96 bits per byte)",
+                "duplicate gAMA chunk",
+                "oversized ancillary chunk tEXt (65537 bytes)",
+                "13 bytes after IEND",
+            ]
+        );
+        assert_eq!(report.anomalies[0].chunk, Some(2));
+        assert_eq!(report.score(), 100);
+    }
+
+    #[test]
+    fn test_idat_split_patterns() {
+        let mut png = testing_image(64, 64, 2, 8).to_png().unwrap();
+        let data = png.image_data();
+        let regular: Vec<Vec<u8>> = data.chunks(1000).map(|x| x.to_vec()).collect();
+        png.set_image_chunks(regular).unwrap();
+        assert!(inspect(&png).anomalies.is_empty());
+
+        let parts = vec![
+            data[..10].to_vec(),
+            data[10..500].to_vec(),
+            Vec::new(),
+            data[500..].to_vec(),
+        ];
+        png.set_image_chunks(parts).unwrap();
+        let report = inspect(&png);
+        assert_eq!(report.anomalies.len(), 2);
+        assert_eq!(report.anomalies[0].description, "1 empty IDAT chunks");
+        assert!(report.anomalies[1]
+            .description
+            .starts_with("unusual IDAT split"));
+
+        png.set_image_data(data).unwrap();
+        png.append_chunk(chunk("tEXt", b"Comment\0between".to_vec()));
+        png.append_chunk(Chunk::new(
+            ChunkType::try_from(Png::IDAT).unwrap(),
+            Vec::new(),
+        ));
+        let report = inspect(&png);
+        assert_eq!(
+            report.anomalies[0].description,
+            "IDAT chunks are not consecutive"
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -20,6 +21,11 @@ pub(crate) enum Commands {
         message: String,
         /// Path to the new png file containing the embedded message
         output_file: Option<PathBuf>,
+        #[command(flatten)]
+        method: MethodArgs,
+        /// Unsafe-to-copy message chunk written by encode to keep after the pixels change, can be repeated
+        #[arg(long, value_name = "TYPE")]
+        keep_message: Vec<String>,
     },
 
     /// Fetch the embedded message
@@ -28,6 +34,17 @@ pub(crate) enum Commands {
         file_path: PathBuf,
         /// Type of message chunk
         chunk_type: String,
+        #[command(flatten)]
+        method: MethodArgs,
+        /// Reversible method: write the restored original image to this path
+        #[arg(long)]
+        restore: Option<PathBuf>,
+        /// Unsafe-to-copy message chunk written by encode to keep after the pixels change, can be repeated
+        #[arg(long, value_name = "TYPE")]
+        keep_message: Vec<String>,
+        /// Keep going past damaged chunks and report the damage
+        #[arg(long)]
+        lenient: bool,
     },
 
     /// Delete the given embedded message
@@ -42,5 +59,265 @@ pub(crate) enum Commands {
     Print {
         /// The png file path
         file_path: PathBuf,
+        /// Keep going past damaged chunks and report the damage
+        #[arg(long)]
+        lenient: bool,
     },
+
+    /// Verify the png file structure and image data
+    Check {
+        /// The png file path
+        file_path: PathBuf,
+    },
+
+    /// Report how many message bytes each method can hold
+    Capacity {
+        /// The png file path
+        file_path: PathBuf,
+        /// Key used by the pixel methods (only the reversible method depends on it)
+        #[arg(short, long)]
+        key: Option<String>,
+        /// Alpha method: also count the alpha LSB of near-opaque pixels
+        #[arg(long)]
+        near_opaque: bool,
+    },
+
+    /// Compare the pixels of two png files, exits with status 2 when a threshold is not met
+    Compare {
+        /// The original png file
+        file_path: PathBuf,
+        /// The png file to compare against
+        other_path: PathBuf,
+        /// Write a difference heatmap png
+        #[arg(long)]
+        heatmap: Option<PathBuf>,
+        /// Minimum PSNR in dB over all channels
+        #[arg(long)]
+        min_psnr: Option<f64>,
+        /// Minimum SSIM over all channels
+        #[arg(long)]
+        min_ssim: Option<f64>,
+        /// Maximum absolute sample difference
+        #[arg(long)]
+        max_diff: Option<u16>,
+    },
+
+    /// Estimate the LSB embedding rate per channel (chi-square, RS and sample pair analysis)
+    Analyze {
+        /// The png file path
+        file_path: PathBuf,
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Try extracting data from every bit plane, channel set and scan order (zsteg style)
+    Scan {
+        /// The png file path
+        file_path: PathBuf,
+        /// Show every candidate, not only those with a recognised magic or mostly printable text
+        #[arg(long)]
+        all: bool,
+        /// Write the full stream of one candidate, e.g. b0,rgb,msb,xy
+        #[arg(long, requires = "output")]
+        extract: Option<String>,
+        /// Output file for --extract
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Write one black-and-white png per channel bit plane (R0.png to A7.png)
+    Bitplanes {
+        /// The png file path
+        file_path: PathBuf,
+        /// Directory for the bit plane images
+        #[arg(long)]
+        out: PathBuf,
+        /// Show only the bits that differ from this png file
+        #[arg(long)]
+        diff: Option<PathBuf>,
+    },
+
+    /// Score png files for suspicious chunks, trailers and IDAT layouts
+    Anomalies {
+        /// The png file paths
+        #[arg(required = true)]
+        file_paths: Vec<PathBuf>,
+    },
+
+    /// Search all chunk data for keys and credentials, exits with status 2 when something is found
+    Secrets {
+        /// The png file path
+        file_path: PathBuf,
+        /// Extra pattern as NAME=REGEX with NAME in [a-z0-9-]+, can be repeated
+        #[arg(short, long)]
+        pattern: Vec<String>,
+        /// Print the findings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Remove every chunk that is not needed to render the image and report what was removed
+    Strip {
+        /// The png file path
+        file_path: PathBuf,
+        /// Path to the stripped png file
+        output_file: Option<PathBuf>,
+        /// Which chunks to keep besides the critical, transparency and pixel aspect chunks
+        #[arg(short, long, value_enum, default_value_t = StripProfile::Color)]
+        profile: StripProfile,
+        /// Allowlist profile: ancillary chunk types to keep, comma separated
+        #[arg(short, long, value_delimiter = ',')]
+        keep: Vec<String>,
+    },
+
+    /// Apply a TOML chunk policy, exits with status 2 and writes nothing when the file is rejected
+    Enforce {
+        /// The png file path
+        file_path: PathBuf,
+        /// The TOML policy file
+        #[arg(long)]
+        policy: PathBuf,
+        /// Path to the sanitized png file
+        output_file: Option<PathBuf>,
+    },
+
+    /// Repair newline conversion, bad CRCs and a missing IEND, exits with status 2 and writes nothing when the repair fails
+    Repair {
+        /// The damaged png file path
+        file_path: PathBuf,
+        /// Path to the repaired png file
+        output_file: Option<PathBuf>,
+    },
+
+    /// Extract png files from an arbitrary binary blob and write a manifest of their offsets
+    Carve {
+        /// The blob to search, e.g. a memory dump or disk image
+        file_path: PathBuf,
+        /// Directory for the extracted png files and manifest.json
+        #[arg(long)]
+        out: PathBuf,
+        /// Also recover incomplete or damaged png files
+        #[arg(long)]
+        lenient: bool,
+    },
+
+    /// Append a zip archive so the file is both a png and a valid zip
+    Polyglot {
+        /// The png file path
+        file_path: PathBuf,
+        /// The zip file to append
+        zip_path: PathBuf,
+        /// Path to the new polyglot file
+        output_file: Option<PathBuf>,
+    },
+
+    /// Embed a fragile watermark that reveals which 8x8 blocks were modified
+    Watermark {
+        /// The png file path
+        file_path: PathBuf,
+        /// Path to the watermarked png file
+        output_file: Option<PathBuf>,
+        /// Key used to compute the block hashes
+        #[arg(short, long)]
+        key: String,
+        /// Unsafe-to-copy message chunk written by encode to keep after the pixels change, can be repeated
+        #[arg(long, value_name = "TYPE")]
+        keep_message: Vec<String>,
+    },
+
+    /// Check a fragile watermark and show which blocks were modified
+    VerifyWatermark {
+        /// The png file path
+        file_path: PathBuf,
+        /// Key used to compute the block hashes
+        #[arg(short, long)]
+        key: String,
+        /// Write a png mask with the modified blocks in white
+        #[arg(long)]
+        mask: Option<PathBuf>,
+    },
+
+    /// Embed a robust 64-bit ownership mark spread across the luminance
+    Mark {
+        /// The png file path
+        file_path: PathBuf,
+        /// The 64-bit ID in hexadecimal
+        id: String,
+        /// Path to the marked png file
+        output_file: Option<PathBuf>,
+        /// Key used to generate the pseudo-noise pattern
+        #[arg(short, long)]
+        key: String,
+        /// Luminance change per pixel, on a 0-255 scale
+        #[arg(short, long, default_value_t = 2)]
+        strength: u8,
+        /// Unsafe-to-copy message chunk written by encode to keep after the pixels change, can be repeated
+        #[arg(long, value_name = "TYPE")]
+        keep_message: Vec<String>,
+    },
+
+    /// Detect a robust ownership mark and report the ID with a confidence score
+    Detect {
+        /// The png file path
+        file_path: PathBuf,
+        /// Key used to generate the pseudo-noise pattern
+        #[arg(short, long)]
+        key: String,
+    },
+}
+
+#[derive(clap::Args)]
+pub(crate) struct MethodArgs {
+    /// Where the message is stored
+    #[arg(short, long, value_enum, default_value_t = Method::Chunk)]
+    pub(crate) method: Method,
+    /// Key used by the pixel methods
+    #[arg(short, long)]
+    pub(crate) key: Option<String>,
+    /// Alpha method: also use the alpha LSB of near-opaque pixels
+    #[arg(long)]
+    pub(crate) near_opaque: bool,
+}
+
+impl MethodArgs {
+    pub(crate) fn key(&self) -> Result<&str> {
+        self.key
+            .as_deref()
+            .with_context(|| "A key is required for this method")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Method {
+    /// Store the message in its own chunk
+    Chunk,
+    /// Hamming matrix embedding in pixel LSBs, fewer pixel changes than plain LSB
+    Matrix,
+    /// EzStego-style embedding in palette index parity, for indexed-color images
+    Palette,
+    /// Color channels of fully transparent pixels (tRNS aware for indexed images)
+    Alpha,
+    /// Per-row filter type choice, pixels stay bit-identical (low capacity)
+    FilterType,
+    /// Lengths of re-split IDAT chunks, no key needed
+    IdatSplit,
+    /// Permutation of existing ancillary chunks, short messages only, no key needed
+    ChunkOrder,
+    /// Empty deflate blocks at the start of the IDAT stream, no key needed
+    Zlib,
+    /// Append the message after IEND, no key needed
+    Trailer,
+    /// Histogram shifting in pixel values, the original image can be restored exactly
+    Reversible,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum StripProfile {
+    /// Keep only critical chunks (and tRNS, pHYs), color management chunks and eXIf are dropped too
+    Critical,
+    /// Also keep color management chunks (gAMA, cHRM, sRGB, iCCP, ...) and the eXIf orientation
+    Color,
+    /// Keep color management chunks and the types given with --keep
+    Allowlist,
 }
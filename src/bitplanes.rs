@@ -0,0 +1,100 @@
+use crate::image::{Ihdr, Image, Planes};
+use anyhow::{bail, Result};
+
+/*
+把每个通道的每个比特平面输出为一张黑白图像（比特为 1 的像素为白色），名称如 R0、A7，0 为最低位。
+LSB 隐写在平滑区域的最低位平面上通常一眼就能看出来。
+给出另一张图像时输出两者比特平面的异或，只有不同的比特为白色，即使改动很少也很明显。
+ */
+pub(crate) fn bit_planes(planes: &Planes, other: Option<&Planes>) -> Result<Vec<(String, Image)>> {
+    if let Some(other) = other {
+        if (planes.width, planes.height) != (other.width, other.height) {
+            bail!(
+                "Image sizes differ: {}x{} and {}x{}",
+                planes.width,
+                planes.height,
+                other.width,
+                other.height
+            );
+        }
+        if planes.names != other.names || planes.max != other.max {
+            bail!("Images have different channels or bit depths");
+        }
+    }
+    let bits = 16 - planes.max.leading_zeros();
+    let ihdr = Ihdr {
+        width: planes.width as u32,
+        height: planes.height as u32,
+        bit_depth: 1,
+        color_type: 0,
+        interlace: 0,
+    };
+
+    let mut images = Vec::new();
+    for (channel, (&name, samples)) in planes.names.iter().zip(&planes.planes).enumerate() {
+        for bit in 0..bits {
+            let mut image = Image {
+                ihdr,
+                data: vec![0; ihdr.stride() * planes.height],
+                filters: vec![0; planes.height],
+            };
+            for (pixel, &sample) in samples.iter().enumerate() {
+                let other = other.map_or(0, |x| x.planes[channel][pixel]);
+                image.set_index(pixel, ((sample ^ other) >> bit) as u8 & 1);
+            }
+            images.push((format!("{}{}", name, bit), image));
+        }
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+
+    #[test]
+    fn test_bit_planes() {
+        let image = testing_image(10, 3, 6, 8);
+        let planes = Planes::new(&image, None).unwrap();
+        let images = bit_planes(&planes, None).unwrap();
+        let names: Vec<&str> = images.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names.len(), 32);
+        assert_eq!(names[..2], ["R0", "R1"]);
+        assert_eq!(names[31], "A7");
+
+        // G 通道第 3 位
+        let (_, plane) = &images[8 + 3];
+        for pixel in 0..30 {
+            assert_eq!(plane.index(pixel), (image.sample(pixel, 1) >> 3) as u8 & 1);
+        }
+    }
+
+    #[test]
+    fn test_bit_plane_differences() {
+        let image = testing_image(16, 16, 0, 8);
+        let mut changed = image.clone();
+        changed.data[37] ^= 1;
+        changed.data[200] ^= 0x80;
+        let images = bit_planes(
+            &Planes::new(&image, None).unwrap(),
+            Some(&Planes::new(&changed, None).unwrap()),
+        )
+        .unwrap();
+        let white = |plane: &Image| {
+            (0..256)
+                .filter(|&pixel| plane.index(pixel) == 1)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(white(&images[0].1), [37]);
+        assert_eq!(white(&images[7].1), [200]);
+        assert!(white(&images[3].1).is_empty());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let a = Planes::new(&testing_image(8, 8, 0, 8), None).unwrap();
+        let b = Planes::new(&testing_image(8, 9, 0, 8), None).unwrap();
+        assert!(bit_planes(&a, Some(&b)).is_err());
+    }
+}
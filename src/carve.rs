@@ -0,0 +1,324 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::recovery::{self, Damage};
+use serde::Serialize;
+
+/*
+从内存转储、磁盘镜像、抓包等任意数据中提取 PNG：
+查找每个签名，从签名开始逐个校验块的 CRC 直到 IEND，得到完整的图像。
+中途遇到 CRC 错误、无法解析或被截断的块时图像不完整，宽松模式下用 recovery 从签名到下一个签名
+（或数据末尾）之间尽量恢复，否则只记录偏移。
+恢复时图像在第一个被截断、无法解析或长度超出真正数据的块之前结束，后面的数据与这张图像无关。
+ */
+pub(crate) struct Carved {
+    pub(crate) offset: usize,
+    // 在原数据中占用的字节数
+    pub(crate) len: usize,
+    pub(crate) png: Png,
+    // 为空表示图像完整
+    pub(crate) damage: Vec<Damage>,
+}
+
+pub(crate) struct Carving {
+    pub(crate) images: Vec<Carved>,
+    // 非宽松模式下跳过的不完整图像的偏移
+    pub(crate) skipped: Vec<usize>,
+}
+
+// 清单中的一项，跳过的图像没有文件和长度
+#[derive(Serialize)]
+pub(crate) struct Entry {
+    pub(crate) file: Option<String>,
+    pub(crate) offset: usize,
+    pub(crate) length: Option<usize>,
+    pub(crate) complete: bool,
+    pub(crate) damage: Vec<String>,
+}
+
+impl Carved {
+    pub(crate) fn entry(&self, file: String) -> Entry {
+        Entry {
+            file: Some(file),
+            offset: self.offset,
+            length: Some(self.len),
+            complete: self.damage.is_empty(),
+            damage: self.damage.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+impl Carving {
+    // 按偏移排序的清单，files 与 images 一一对应
+    pub(crate) fn manifest(&self, files: Vec<String>) -> Vec<Entry> {
+        let mut manifest: Vec<Entry> = self
+            .images
+            .iter()
+            .zip(files)
+            .map(|(carved, file)| carved.entry(file))
+            .chain(self.skipped.iter().map(|&offset| Entry {
+                file: None,
+                offset,
+                length: None,
+                complete: false,
+                damage: Vec::new(),
+            }))
+            .collect();
+        manifest.sort_by_key(|x| x.offset);
+        manifest
+    }
+}
+
+fn find_signature(bytes: &[u8], from: usize) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(Png::STANDARD_HEADER.len())
+        .position(|x| x == Png::STANDARD_HEADER)
+        .map(|x| x + from)
+}
+
+// 从 start 处的签名开始逐个校验块，到 IEND 为止都完好时返回结束位置
+fn walk(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut offset = start + Png::STANDARD_HEADER.len();
+    loop {
+        let length = bytes.get(offset..offset.checked_add(4)?)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let end = offset.checked_add(length)?.checked_add(12)?;
+        let chunk = Chunk::try_from(bytes.get(offset..end)?).ok()?;
+        offset = end;
+        if chunk.chunk_type().bytes() == Png::IEND {
+            return Some(offset);
+        }
+    }
+}
+
+// 不完整的图像，slice 从签名开始；没有可用的块时返回 None
+fn partial(slice: &[u8], offset: usize) -> Option<Carved> {
+    let recovered = recovery::recover(slice);
+    let stop = recovered.damage.iter().position(|x| {
+        matches!(
+            x,
+            Damage::Garbage { .. } | Damage::Truncated { .. } | Damage::Overrun { .. }
+        )
+    });
+    let Some(stop) = stop else {
+        // 只有 CRC 错误，图像到 IEND 为止
+        let len = slice.len() - recovered.png.trailer().len();
+        let mut png = recovered.png;
+        png.set_trailer(Vec::new());
+        return Some(Carved {
+            offset,
+            len,
+            png,
+            damage: recovered.damage,
+        });
+    };
+    let end = match recovered.damage[stop] {
+        Damage::Garbage { offset, .. }
+        | Damage::Truncated { offset, .. }
+        | Damage::Overrun { offset, .. } => offset,
+        _ => unreachable!(),
+    };
+
+    // 签名有效，出问题之前的块是连续的
+    let mut png = recovered.png;
+    let mut len = Png::STANDARD_HEADER.len();
+    let mut done = false;
+    png.remove_chunks(|chunk| {
+        done = done || len + chunk.data().len() + 12 > end;
+        if !done {
+            len += chunk.data().len() + 12;
+        }
+        done
+    });
+    png.set_trailer(Vec::new());
+    if png.chunks().is_empty() {
+        return None;
+    }
+    // 补上 IEND，提取出的文件结构完整；len 仍然是在原数据中占用的字节数
+    png.ensure_iend().ok()?;
+    let mut damage = recovered.damage;
+    damage.truncate(stop + 1);
+    damage.push(Damage::MissingIend);
+    Some(Carved {
+        offset,
+        len,
+        png,
+        damage,
+    })
+}
+
+pub(crate) fn carve(bytes: &[u8], lenient: bool) -> Carving {
+    let mut images = Vec::new();
+    let mut skipped = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = find_signature(bytes, from) {
+        if let Some(end) = walk(bytes, offset) {
+            if let Ok(png) = Png::try_from(&bytes[offset..end]) {
+                images.push(Carved {
+                    offset,
+                    len: end - offset,
+                    png,
+                    damage: Vec::new(),
+                });
+                from = end;
+                continue;
+            }
+        }
+
+        // 不完整的图像，最多恢复到下一个签名之前
+        from = offset + Png::STANDARD_HEADER.len();
+        if !lenient {
+            skipped.push(offset);
+            continue;
+        }
+        let next = find_signature(bytes, from).unwrap_or(bytes.len());
+        let Some(carved) = partial(&bytes[offset..next], offset) else {
+            skipped.push(offset);
+            continue;
+        };
+        from = offset + carved.len;
+        images.push(carved);
+    }
+    Carving { images, skipped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use std::str::FromStr;
+
+    fn testing_bytes(message: &str) -> Vec<u8> {
+        let mut png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            message.as_bytes().to_vec(),
+        ));
+        png.as_bytes()
+    }
+
+    // 垃圾数据、完整的图像、垃圾数据、被截断的图像
+    fn testing_blob() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let first = testing_bytes("first");
+        let second = testing_bytes("second");
+        let blob = [
+            &[0xde; 100][..],
+            &first,
+            b"\x89PNG garbage",
+            &second[..second.len() - 20],
+        ]
+        .concat();
+        (blob, first, second)
+    }
+
+    #[test]
+    fn test_carve_complete_images() {
+        let (blob, first, _) = testing_blob();
+        let carving = carve(&blob, false);
+        assert_eq!(carving.images.len(), 1);
+        let image = &carving.images[0];
+        assert_eq!(image.offset, 100);
+        assert_eq!(image.len, first.len());
+        assert!(image.damage.is_empty());
+        assert_eq!(image.png.as_bytes(), first);
+        assert_eq!(carving.skipped, [100 + first.len() + 12]);
+    }
+
+    #[test]
+    fn test_carve_lenient() {
+        let (blob, first, second) = testing_blob();
+        let carving = carve(&blob, true);
+        assert_eq!(carving.images.len(), 2);
+        assert!(carving.skipped.is_empty());
+        let image = &carving.images[1];
+        assert_eq!(image.offset, 100 + first.len() + 12);
+        // 图像在被截断的 ruSt 块之前结束
+        assert_eq!(image.len, second.len() - 12 - 18);
+        assert_eq!(
+            image.damage,
+            [
+                Damage::Truncated {
+                    offset: second.len() - 12 - 18,
+                    chunk_type: Some("ruSt".to_string()),
+                    present: 10,
+                    expected: 18
+                },
+                Damage::MissingIend
+            ]
+        );
+        assert!(Image::try_from(&image.png).is_ok());
+        // 提取出的文件以 IEND 结束，能按严格方式解析
+        assert_eq!(
+            image.png.chunks().last().unwrap().chunk_type().bytes(),
+            Png::IEND
+        );
+        assert!(Png::try_from(image.png.as_bytes().as_ref()).is_ok());
+
+        let entry = image.entry("partial.png".to_string());
+        assert!(!entry.complete);
+        assert_eq!(entry.damage.len(), 2);
+    }
+
+    #[test]
+    fn test_manifest_lists_skipped() {
+        let (blob, first, _) = testing_blob();
+        let carving = carve(&blob, false);
+        let manifest = carving.manifest(vec!["00000064.png".to_string()]);
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(manifest[0].file.as_deref(), Some("00000064.png"));
+        assert!(manifest[0].complete);
+        assert_eq!(manifest[1].file, None);
+        assert_eq!(manifest[1].offset, 100 + first.len() + 12);
+        assert_eq!(manifest[1].length, None);
+        assert!(!manifest[1].complete);
+        let json = serde_json::to_string(&manifest[1]).unwrap();
+        assert!(json.contains("\"file\":null"));
+    }
+
+    #[test]
+    fn test_truncated_image_in_noise() {
+        // 被截断的图像后面是无关的数据，IDAT 的长度超出了真正的数据
+        let bytes = testing_bytes("message");
+        let idat_end = bytes.len() - 12 - 19;
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect();
+        let blob = [&bytes[..idat_end - 30], &noise].concat();
+        let carving = carve(&blob, true);
+        assert_eq!(carving.images.len(), 1);
+        let image = &carving.images[0];
+        assert_eq!(image.len, 8 + 25);
+        assert_eq!(image.png.chunks().len(), 2);
+        assert_eq!(
+            image.damage,
+            [
+                Damage::Overrun {
+                    offset: 8 + 25,
+                    chunk_type: "IDAT".to_string()
+                },
+                Damage::MissingIend
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signature_inside_chunk() {
+        // 块数据中的签名不会被当作另一张图像
+        let mut outer = testing_image(16, 16, 2, 8).to_png().unwrap();
+        outer.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            testing_bytes("inner"),
+        ));
+        let carving = carve(&outer.as_bytes(), true);
+        assert_eq!(carving.images.len(), 1);
+        assert!(carving.images[0].damage.is_empty());
+    }
+}
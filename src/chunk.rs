@@ -38,7 +38,7 @@ impl TryFrom<&[u8]> for Chunk {
         let (crc_bytes, rest) = rest
             .split_at_checked(4)
             .with_context(|| "CRC bytes length must be 4")?;
-        if rest.len() != 0 {
+        if !rest.is_empty() {
             bail!("Invalid bytes length");
         }
         let input_crc = u32::from_be_bytes(crc_bytes.try_into()?);
@@ -79,7 +79,7 @@ impl Chunk {
         }
     }
 
-    fn length(&self) -> u32 {
+    pub(crate) fn length(&self) -> u32 {
         self.data_length
     }
 
@@ -87,16 +87,17 @@ impl Chunk {
         &self.chunk_type
     }
 
-    fn data(&self) -> &[u8] {
+    pub(crate) fn data(&self) -> &[u8] {
         &self.data
     }
 
+    #[allow(dead_code)]
     fn crc(&self) -> u32 {
         self.crc
     }
 
     pub(crate) fn data_as_string(&self) -> Result<String> {
-        Ok(String::from_utf8(self.data.to_vec()).with_context(|| "Invalid utf-8 sequence")?)
+        String::from_utf8(self.data.to_vec()).with_context(|| "Invalid utf-8 sequence")
     }
 
     pub(crate) fn as_bytes(&self) -> Vec<u8> {
@@ -0,0 +1,240 @@
+use crate::png::Png;
+use anyhow::{bail, Result};
+
+/*
+辅助块之间的先后顺序没有意义，n 个辅助块的排列方式可以携带 log2(n!) 个比特。
+PNG 规范对辅助块位置的限制都是相对关键块（PLTE、IDAT）而言的，
+所以只在相邻两个关键块之间的辅助块内部重新排列，不改变它们与关键块的相对位置。
+
+每一段中的辅助块按字节内容排序得到标准顺序，实际顺序用 Lehmer 码表示为混合进制的数字，
+所有段的数字合起来就是一个大整数，即 消息长度(1) + 消息 的大端表示。
+包含完全相同的辅助块的段无法区分顺序，不参与嵌入。
+ */
+fn segments(png: &Png) -> Vec<Vec<usize>> {
+    let mut segments = vec![Vec::new()];
+    for (i, chunk) in png.chunks().iter().enumerate() {
+        if chunk.chunk_type().is_critical() {
+            segments.push(Vec::new());
+        } else if let Some(segment) = segments.last_mut() {
+            segment.push(i);
+        }
+    }
+    segments
+        .into_iter()
+        .filter(|segment| {
+            let mut bytes: Vec<Vec<u8>> = segment
+                .iter()
+                .map(|&i| png.chunks()[i].as_bytes())
+                .collect();
+            bytes.sort();
+            bytes.dedup();
+            segment.len() >= 2 && bytes.len() == segment.len()
+        })
+        .collect()
+}
+
+// 段中辅助块的标准顺序（按字节内容排序）
+fn canonical(png: &Png, segment: &[usize]) -> Vec<Vec<u8>> {
+    let mut bytes: Vec<Vec<u8>> = segment
+        .iter()
+        .map(|&i| png.chunks()[i].as_bytes())
+        .collect();
+    bytes.sort();
+    bytes
+}
+
+// 每一位 Lehmer 码的进制：n, n-1, ..., 2
+fn radices(segments: &[Vec<usize>]) -> Vec<u32> {
+    segments
+        .iter()
+        .flat_map(|segment| (2..=segment.len() as u32).rev())
+        .collect()
+}
+
+// 大端表示的大整数 num = num * mul + add
+fn mul_add(num: &mut Vec<u8>, mul: u32, add: u32) {
+    let mut carry = add;
+    for byte in num.iter_mut().rev() {
+        let value = *byte as u32 * mul + carry;
+        *byte = value as u8;
+        carry = value >> 8;
+    }
+    while carry > 0 {
+        num.insert(0, carry as u8);
+        carry >>= 8;
+    }
+}
+
+// 大端表示的大整数 num = num / div，返回余数
+fn div_rem(num: &mut Vec<u8>, div: u32) -> u32 {
+    let mut rem = 0;
+    for byte in num.iter_mut() {
+        let value = (rem << 8) | *byte as u32;
+        *byte = (value / div) as u8;
+        rem = value % div;
+    }
+    let leading_zeros = num.iter().take_while(|&&x| x == 0).count();
+    num.drain(..leading_zeros);
+    rem
+}
+
+// 可嵌入的消息字节数
+pub(crate) fn capacity(png: &Png) -> usize {
+    let mut product = vec![1];
+    for radix in radices(&segments(png)) {
+        mul_add(&mut product, radix, 0);
+    }
+    // 大整数必须小于所有进制的乘积，去掉 1 个字节的消息长度
+    let bits = product.len() * 8 - product[0].leading_zeros() as usize;
+    ((bits - 1) / 8).saturating_sub(1).min(255)
+}
+
+pub(crate) fn embed(png: &mut Png, message: &[u8]) -> Result<()> {
+    let segments = segments(png);
+    if segments.is_empty() {
+        bail!("Not enough ancillary chunks to reorder");
+    }
+    let capacity = capacity(png);
+    if message.len() > capacity {
+        bail!(
+            "Message too large: needs {} bytes, the ancillary chunk order can only hold {}",
+            message.len(),
+            capacity
+        );
+    }
+
+    let mut num = [&[message.len() as u8], message].concat();
+    let mut digits = radices(&segments)
+        .into_iter()
+        .map(|radix| div_rem(&mut num, radix) as usize);
+
+    for segment in &segments {
+        let mut remaining = canonical(png, segment);
+        for (j, &slot) in segment.iter().enumerate() {
+            let digit = if j + 1 < segment.len() {
+                digits.next().unwrap_or(0)
+            } else {
+                0
+            };
+            let wanted = remaining.remove(digit);
+            // 把目标块交换到当前位置，之后的位置只会从还没放好的块中选择
+            let current = segment[j..]
+                .iter()
+                .copied()
+                .find(|&i| png.chunks()[i].as_bytes() == wanted)
+                .unwrap_or(slot);
+            png.chunks_mut().swap(slot, current);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn extract(png: &Png) -> Result<Vec<u8>> {
+    let segments = segments(png);
+    if segments.is_empty() {
+        bail!("Not enough ancillary chunks to reorder");
+    }
+
+    let mut digits = Vec::new();
+    for segment in &segments {
+        let mut remaining = canonical(png, segment);
+        for &slot in &segment[..segment.len() - 1] {
+            let bytes = png.chunks()[slot].as_bytes();
+            let digit = remaining.iter().position(|x| *x == bytes).unwrap_or(0);
+            remaining.remove(digit);
+            digits.push(digit as u32);
+        }
+    }
+
+    let mut num = Vec::new();
+    for (radix, digit) in radices(&segments).into_iter().zip(digits).rev() {
+        mul_add(&mut num, radix, digit);
+    }
+    match num.split_first() {
+        None => Ok(Vec::new()),
+        Some((&len, message)) if len as usize == message.len() => Ok(message.to_vec()),
+        _ => bail!("No embedded message found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    fn testing_png_with_chunks(count: usize) -> Png {
+        let mut png = testing_image(4, 4, 2, 8).to_png().unwrap();
+        for i in 0..count {
+            let chunk = Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                format!("Comment\0number {}", i).into_bytes(),
+            );
+            png.append_chunk(chunk);
+        }
+        png
+    }
+
+    #[test]
+    fn test_big_number_round_trip() {
+        let mut num = vec![1, 2, 3];
+        let rem = div_rem(&mut num, 7);
+        mul_add(&mut num, 7, rem);
+        assert_eq!(num, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_chunk_order_round_trip() {
+        let mut png = testing_png_with_chunks(12);
+        assert_eq!(capacity(&png), 2);
+        embed(&mut png, b"Hi").unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(extract(&png).unwrap(), b"Hi");
+        // 没有增加新的块
+        assert_eq!(png.chunks().len(), 15);
+        assert_eq!(png.chunks()[0].chunk_type().to_string(), "IHDR");
+        assert_eq!(png.chunks()[14].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_chunk_order_too_few_chunks() {
+        let mut png = testing_png_with_chunks(1);
+        assert!(embed(&mut png, b"").is_err());
+
+        let mut png = testing_png_with_chunks(5);
+        assert!(embed(&mut png, b"Hi").is_err());
+    }
+
+    #[test]
+    fn test_chunk_order_keeps_critical_positions() {
+        let base = testing_image(4, 4, 2, 8).to_png().unwrap();
+        let mut chunks: Vec<Chunk> = base
+            .chunks()
+            .iter()
+            .map(|x| Chunk::try_from(x.as_bytes().as_ref()).unwrap())
+            .collect();
+        // 在 IDAT 之前加两个辅助块，之后加八个
+        for text in ["before\0a", "before\0b"] {
+            let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), text.into());
+            chunks.insert(1, chunk);
+        }
+        let mut png = Png::from_chunks(chunks);
+        for i in 0..8 {
+            let chunk = Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                format!("after\0{}", i).into_bytes(),
+            );
+            png.append_chunk(chunk);
+        }
+
+        embed(&mut png, b"x").unwrap();
+        assert_eq!(png.chunks()[3].chunk_type().to_string(), "IDAT");
+        assert!(png.chunks()[1..3]
+            .iter()
+            .all(|x| x.data().starts_with(b"before")));
+        assert_eq!(extract(&png).unwrap(), b"x");
+    }
+}
@@ -2,6 +2,14 @@ use anyhow::{bail, Error};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
+// PNG 规范和已注册扩展中的公共块类型
+const REGISTERED: [&[u8; 4]; 31] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"mDCV", b"cLLI", b"tEXt", b"zTXt", b"iTXt", b"bKGD", b"hIST", b"pHYs", b"sPLT",
+    b"eXIf", b"tIME", b"acTL", b"fcTL", b"fdAT", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"gIFg",
+    b"dSIG",
+];
+
 /*
 Chunk Type，4 个字节的块类型代码。
 类型代码仅限于由大写和小写 ASCII 字母（A-Z 和 a-z，或十进制 65-90 和 97-122）组成
@@ -55,25 +63,31 @@ impl ChunkType {
         [self.0, self.1, self.2, self.3]
     }
 
+    #[allow(dead_code)]
     fn is_valid(&self) -> bool {
         self.2.is_ascii_uppercase()
     }
 
-    fn is_critical(&self) -> bool {
+    pub(crate) fn is_critical(&self) -> bool {
         self.0.is_ascii_uppercase()
     }
 
-    fn is_public(&self) -> bool {
+    pub(crate) fn is_public(&self) -> bool {
         self.1.is_ascii_uppercase()
     }
 
-    fn is_reserved_bit_valid(&self) -> bool {
+    pub(crate) fn is_reserved_bit_valid(&self) -> bool {
         self.2.is_ascii_uppercase()
     }
 
-    fn is_safe_to_copy(&self) -> bool {
+    pub(crate) fn is_safe_to_copy(&self) -> bool {
         self.3.is_ascii_lowercase()
     }
+
+    // PNG 规范或已注册的扩展中定义的类型
+    pub(crate) fn is_registered(&self) -> bool {
+        REGISTERED.contains(&&self.bytes())
+    }
 }
 
 #[cfg(test)]
@@ -145,6 +159,12 @@ mod tests {
         assert!(!chunk.is_safe_to_copy());
     }
 
+    #[test]
+    pub fn test_chunk_type_is_registered() {
+        assert!(ChunkType::from_str("tRNS").unwrap().is_registered());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_registered());
+    }
+
     #[test]
     pub fn test_valid_chunk_is_valid() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
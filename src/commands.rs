@@ -1,7 +1,34 @@
+use crate::alpha;
+use crate::analysis::Analysis;
+use crate::anomalies;
+use crate::args::{Method, MethodArgs, StripProfile};
+use crate::bitplanes;
+use crate::carve;
 use crate::chunk::Chunk;
+use crate::chunk_order;
 use crate::chunk_type::ChunkType;
+use crate::filter_type;
+use crate::idat_split;
+use crate::image::{Image, Planes};
+use crate::matrix;
+use crate::metrics::Comparison;
+use crate::palette::{self, Palette};
 use crate::png::Png;
-use anyhow::Result;
+use crate::policy::Policy;
+use crate::recovery;
+use crate::repair;
+use crate::reversible;
+use crate::scan;
+use crate::secrets::Rules;
+use crate::spread_spectrum;
+use crate::stego::PAYLOAD_OVERHEAD;
+use crate::strip;
+use crate::trailer;
+use crate::watermark;
+use crate::zip::{self, Archive};
+use crate::zlib;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
 use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -12,36 +39,97 @@ pub(crate) fn encode_msg(
     chunk_type: String,
     message: String,
     output_file: Option<PathBuf>,
+    method: MethodArgs,
+    keep_message: Vec<String>,
 ) -> Result<()> {
+    // 像素方法中的块类型只保存在像素里，文件中同类型的消息块也保留
+    let keep = chunk_types(&keep_message, Some(&chunk_type))?;
     let file_bytes = read_to_bytes(&file_path)?;
     let mut png = Png::try_from(file_bytes.as_ref())?;
     let chunk = Chunk::new(ChunkType::from_str(&chunk_type)?, message.into_bytes());
-    png.append_chunk(chunk);
-
-    match output_file {
-        Some(output_file) => {
-            let mut wf = File::create(output_file)?;
-            wf.write_all(png.as_bytes().as_ref())?;
+    // idat-split 需要的空间取决于消息内容，由它自己检查
+    if method.method != Method::IdatSplit {
+        let key = method.key.as_deref().unwrap_or_default();
+        if let Some(capacity) = method_capacity(&png, method.method, key, method.near_opaque)? {
+            if chunk.data().len() > capacity {
+                bail!(
+                    "Message too large: needs {} bytes, only {} available",
+                    chunk.data().len(),
+                    capacity
+                );
+            }
         }
-        None => {
-            let temp_p = file_path.with_extension("png.temp");
-            let mut temp_f = File::create(&temp_p)?;
-            temp_f.write_all(png.as_bytes().as_ref())?;
-            fs::rename(temp_p, file_path)?;
+    }
+    match method.method {
+        Method::Chunk => png.append_chunk(chunk),
+        Method::IdatSplit => idat_split::embed(&mut png, &chunk)?,
+        Method::ChunkOrder => chunk_order::embed(&mut png, chunk.data())?,
+        Method::Zlib => zlib::embed(&mut png, &chunk)?,
+        Method::Trailer => trailer::embed(&mut png, &chunk),
+        _ => {
+            let changed = embed_in_pixels(&mut png, &chunk, &method)?;
+            println!("Changed {} samples", changed);
         }
     }
+    // 除了这几种方法，其他方法都会重写 IDAT
+    if !matches!(
+        method.method,
+        Method::Chunk | Method::ChunkOrder | Method::Trailer
+    ) {
+        rewrite_unsafe_to_copy(&mut png, &keep);
+    }
+
+    write_png(&png, output_file.unwrap_or(file_path))?;
     println!("Encode {} successfully", chunk_type);
     Ok(())
 }
 
-pub(crate) fn decode_msg(file_path: PathBuf, chunk_type: String) -> Result<()> {
+pub(crate) fn decode_msg(
+    file_path: PathBuf,
+    chunk_type: String,
+    method: MethodArgs,
+    restore: Option<PathBuf>,
+    keep_message: Vec<String>,
+    lenient: bool,
+) -> Result<()> {
+    if restore.is_some() && method.method != Method::Reversible {
+        bail!("Only the reversible method can restore the original image");
+    }
+    let keep = chunk_types(&keep_message, Some(&chunk_type))?;
     let file_bytes = read_to_bytes(file_path)?;
-    let png = Png::try_from(file_bytes.as_ref())?;
-    let msg_chunk = png.chunk_by_type(&chunk_type);
+    let mut png = parse_png(&file_bytes, lenient)?;
+    let msg_chunk = match method.method {
+        Method::Chunk => png.chunk_by_type(&chunk_type).map(|x| x.to_string()),
+        _ => {
+            let chunk = match method.method {
+                Method::IdatSplit => idat_split::extract(&png)?,
+                Method::Zlib => zlib::extract(&png)?,
+                Method::Trailer => trailer::extract(&png, &ChunkType::from_str(&chunk_type)?)?,
+                // 块顺序中没有保存块类型，直接使用给定的类型
+                Method::ChunkOrder => Chunk::new(
+                    ChunkType::from_str(&chunk_type)?,
+                    chunk_order::extract(&png)?,
+                ),
+                Method::Reversible if restore.is_some() => {
+                    let mut image = Image::try_from(&png)?;
+                    let chunk = reversible::restore(&mut image, method.key()?)?;
+                    image.write_to(&mut png)?;
+                    rewrite_unsafe_to_copy(&mut png, &keep);
+                    chunk
+                }
+                _ => extract_from_pixels(&png, &method)?,
+            };
+            (chunk.chunk_type().to_string() == chunk_type).then(|| chunk.to_string())
+        }
+    };
     match msg_chunk {
         Some(chunk) => println!("{}: {}", chunk_type, chunk),
         None => println!("No such chunk"),
     }
+    if let Some(restore) = restore {
+        write_png(&png, &restore)?;
+        println!("Original image restored to {}", restore.display());
+    }
     Ok(())
 }
 
@@ -50,10 +138,7 @@ pub(crate) fn remove_msg(file_path: PathBuf, chunk_type: String) -> Result<()> {
     let mut png = Png::try_from(file_bytes.as_ref())?;
     match png.remove_first_chunk(&chunk_type) {
         Ok(_) => {
-            let temp_p = file_path.with_extension("png.temp");
-            let mut temp_f = File::create(&temp_p)?;
-            temp_f.write_all(png.as_bytes().as_ref())?;
-            fs::rename(temp_p, file_path)?;
+            write_png(&png, file_path)?;
             println!("Removed {}", chunk_type);
         }
         Err(e) => println!("{e}"),
@@ -61,13 +146,535 @@ pub(crate) fn remove_msg(file_path: PathBuf, chunk_type: String) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn print_msg(file_path: PathBuf) -> Result<()> {
+pub(crate) fn print_msg(file_path: PathBuf, lenient: bool) -> Result<()> {
     let file_bytes = read_to_bytes(file_path)?;
-    let png = Png::try_from(file_bytes.as_ref())?;
+    let png = parse_png(&file_bytes, lenient)?;
     println!("{png}");
     Ok(())
 }
 
+pub(crate) fn check_png(file_path: PathBuf) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    // 签名和每个块的 CRC 在解析时检查
+    let png = Png::try_from(file_bytes.as_ref())?;
+    if png
+        .chunks()
+        .last()
+        .is_none_or(|x| x.chunk_type().bytes() != Png::IEND)
+    {
+        bail!("IEND must be the last chunk");
+    }
+    // IHDR 以及图像数据能否完整解码
+    let image = Image::try_from(&png)?;
+    println!(
+        "OK: {}x{}, color type {}, bit depth {}, {} chunks",
+        image.ihdr.width,
+        image.ihdr.height,
+        image.ihdr.color_type,
+        image.ihdr.bit_depth,
+        png.chunks().len()
+    );
+    if !png.trailer().is_empty() {
+        println!("Trailer: {} bytes after IEND", png.trailer().len());
+        // ZIP 的偏移相对文件开头，所以按整个文件的偏移解析
+        let base = file_bytes.len() - png.trailer().len();
+        if let Ok(archive) = Archive::parse(png.trailer(), base) {
+            println!("Archive: valid zip with {} entries", archive.entries());
+        } else if let Ok(archive) = Archive::parse(png.trailer(), 0) {
+            println!(
+                "Archive: zip with {} entries, offsets not adjusted for the png prefix",
+                archive.entries()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn capacity_report(
+    file_path: PathBuf,
+    key: Option<String>,
+    near_opaque: bool,
+) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let png = Png::try_from(file_bytes.as_ref())?;
+    let key = key.unwrap_or_default();
+    for &method in Method::value_variants() {
+        let name = method.to_possible_value().map(|x| x.get_name().to_string());
+        let capacity = match method_capacity(&png, method, &key, near_opaque) {
+            Ok(Some(capacity)) if method == Method::IdatSplit => {
+                format!(
+                    "{} bytes (any message), more for small byte values",
+                    capacity
+                )
+            }
+            Ok(Some(capacity)) => format!("{} bytes", capacity),
+            Ok(None) => "unlimited".to_string(),
+            Err(e) => format!("n/a ({})", e),
+        };
+        println!("{:<12} {}", name.unwrap_or_default(), capacity);
+    }
+    /*
+    普通 LSB 替换（每个颜色样本使用低 1 到 4 位）的容量，只作参考，本工具不提供这种方法。
+    没有自适应嵌入和加密，所以既没有对应的行，也没有加密开销。
+     */
+    let positions = Image::try_from(&png).and_then(|x| x.lsb_positions());
+    for bits in 1..=4 {
+        let capacity = match &positions {
+            Ok(positions) => format!(
+                "{} bytes (reference only)",
+                (positions.len() * bits / 8).saturating_sub(PAYLOAD_OVERHEAD)
+            ),
+            Err(e) => format!("n/a ({})", e),
+        };
+        println!("{:<12} {}", format!("lsb-{}", bits), capacity);
+    }
+    Ok(())
+}
+
+// 返回是否满足所有阈值
+pub(crate) fn compare_png(
+    file_path: PathBuf,
+    other_path: PathBuf,
+    heatmap: Option<PathBuf>,
+    min_psnr: Option<f64>,
+    min_ssim: Option<f64>,
+    max_diff: Option<u16>,
+) -> Result<bool> {
+    let mut planes = Vec::with_capacity(2);
+    for path in [file_path, other_path] {
+        let file_bytes = read_to_bytes(path)?;
+        let png = Png::try_from(file_bytes.as_ref())?;
+        let palette = Palette::try_from(&png).ok();
+        planes.push(Planes::new(&Image::try_from(&png)?, palette.as_ref())?);
+    }
+    let comparison = Comparison::new(&planes[0], &planes[1])?;
+    println!("{}", comparison);
+    if let Some(heatmap) = heatmap {
+        write_png(&comparison.heatmap().to_png()?, heatmap)?;
+    }
+
+    let mut passed = true;
+    if min_psnr.is_some_and(|x| comparison.psnr() < x) {
+        println!(
+            "FAIL: PSNR {:.2} dB is below {}",
+            comparison.psnr(),
+            min_psnr.unwrap_or_default()
+        );
+        passed = false;
+    }
+    if min_ssim.is_some_and(|x| comparison.ssim() < x) {
+        println!(
+            "FAIL: SSIM {:.5} is below {}",
+            comparison.ssim(),
+            min_ssim.unwrap_or_default()
+        );
+        passed = false;
+    }
+    if max_diff.is_some_and(|x| comparison.max_diff() > x) {
+        println!(
+            "FAIL: max difference {} is above {}",
+            comparison.max_diff(),
+            max_diff.unwrap_or_default()
+        );
+        passed = false;
+    }
+    Ok(passed)
+}
+
+pub(crate) fn analyze_png(file_path: PathBuf, json: bool) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let png = Png::try_from(file_bytes.as_ref())?;
+    let palette = Palette::try_from(&png).ok();
+    let planes = Planes::new(&Image::try_from(&png)?, palette.as_ref())?;
+    let analysis = Analysis::new(&planes);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&analysis)?);
+    } else {
+        println!("{}", analysis);
+    }
+    Ok(())
+}
+
+pub(crate) fn scan_png(
+    file_path: PathBuf,
+    all: bool,
+    extract: Option<String>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let png = Png::try_from(file_bytes.as_ref())?;
+    let image = Image::try_from(&png)?;
+    if let (Some(spec), Some(output)) = (extract, output) {
+        let data = scan::extract(&image, &spec.parse()?)?;
+        fs::write(output, data)?;
+        return Ok(());
+    }
+
+    let candidates = scan::scan(&image)?;
+    let mut shown = 0;
+    for candidate in candidates.iter().filter(|x| all || x.kind.is_some()) {
+        println!("{}", candidate);
+        shown += 1;
+    }
+    if shown == 0 {
+        println!("Nothing found in {} candidates", candidates.len());
+    }
+    Ok(())
+}
+
+pub(crate) fn bit_planes(file_path: PathBuf, out: PathBuf, diff: Option<PathBuf>) -> Result<()> {
+    let mut planes = Vec::with_capacity(2);
+    for path in std::iter::once(file_path).chain(diff) {
+        let file_bytes = read_to_bytes(path)?;
+        let png = Png::try_from(file_bytes.as_ref())?;
+        let palette = Palette::try_from(&png).ok();
+        planes.push(Planes::new(&Image::try_from(&png)?, palette.as_ref())?);
+    }
+    fs::create_dir_all(&out)?;
+    let images = bitplanes::bit_planes(&planes[0], planes.get(1))?;
+    for (name, image) in &images {
+        write_png(&image.to_png()?, out.join(format!("{}.png", name)))?;
+    }
+    println!("Wrote {} bit planes to {}", images.len(), out.display());
+    Ok(())
+}
+
+pub(crate) fn anomaly_report(file_paths: Vec<PathBuf>) -> Result<()> {
+    for file_path in file_paths {
+        let file_bytes = read_to_bytes(&file_path)?;
+        let png = Png::try_from(file_bytes.as_ref())?;
+        println!("{}", file_path.display());
+        println!("{}", anomalies::inspect(&png));
+    }
+    Ok(())
+}
+
+// 返回是否没有发现任何密钥
+pub(crate) fn scan_secrets(file_path: PathBuf, patterns: Vec<String>, json: bool) -> Result<bool> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let png = Png::try_from(file_bytes.as_ref())?;
+    let findings = Rules::new(&patterns)?.scan(&png);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else if findings.is_empty() {
+        println!("No secrets found");
+    } else {
+        for finding in &findings {
+            println!("{}", finding);
+        }
+    }
+    Ok(findings.is_empty())
+}
+
+pub(crate) fn strip_png(
+    file_path: PathBuf,
+    output_file: Option<PathBuf>,
+    profile: StripProfile,
+    keep: Vec<String>,
+) -> Result<()> {
+    if !keep.is_empty() && profile != StripProfile::Allowlist {
+        bail!("--keep requires the allowlist profile");
+    }
+    for chunk_type in &keep {
+        ChunkType::from_str(chunk_type)?;
+    }
+    let file_bytes = read_to_bytes(&file_path)?;
+    let mut png = Png::try_from(file_bytes.as_ref())?;
+    let stripped = strip::strip(&mut png, profile != StripProfile::Critical, &keep)?;
+    write_png(&png, output_file.unwrap_or(file_path))?;
+    println!("{}", stripped);
+    Ok(())
+}
+
+// 返回文件是否被接受
+pub(crate) fn enforce_policy(
+    file_path: PathBuf,
+    policy: PathBuf,
+    output_file: Option<PathBuf>,
+) -> Result<bool> {
+    let policy = fs::read_to_string(&policy)
+        .with_context(|| format!("Failed to read {}", policy.display()))?;
+    let policy = Policy::from_str(&policy)?;
+    let file_bytes = read_to_bytes(&file_path)?;
+    let mut png = Png::try_from(file_bytes.as_ref())?;
+    let report = policy.apply(&mut png);
+    println!("{}", report);
+    if report.is_accepted() {
+        write_png(&png, output_file.unwrap_or(file_path))?;
+    }
+    Ok(report.is_accepted())
+}
+
+// 返回修复是否成功，不成功时不写入文件
+pub(crate) fn repair_png(file_path: PathBuf, output_file: Option<PathBuf>) -> Result<bool> {
+    let file_bytes = read_to_bytes(&file_path)?;
+    let repaired = repair::repair(&file_bytes)?;
+    if repaired.fixes.is_empty() && repaired.unrepaired.is_empty() {
+        println!("Nothing to repair");
+    }
+    for fix in &repaired.fixes {
+        println!("Fixed: {}", fix);
+    }
+    for damage in &repaired.unrepaired {
+        println!("Could not repair: {}", damage);
+    }
+    // 修复后的文件必须能按严格方式解析并解码出图像
+    let png = Png::try_from(repaired.png.as_bytes().as_ref())?;
+    if let Err(e) = Image::try_from(&png) {
+        println!("The repaired file still does not decode: {}", e);
+        return Ok(false);
+    }
+    if !repaired.unrepaired.is_empty() {
+        return Ok(false);
+    }
+    println!("Verified: the repaired file decodes");
+    write_png(&png, output_file.unwrap_or(file_path))?;
+    Ok(true)
+}
+
+pub(crate) fn carve_blob(file_path: PathBuf, out: PathBuf, lenient: bool) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let carving = carve::carve(&file_bytes, lenient);
+    fs::create_dir_all(&out)?;
+    let mut files = Vec::with_capacity(carving.images.len());
+    for carved in &carving.images {
+        let file = format!("{:08x}.png", carved.offset);
+        write_png(&carved.png, out.join(&file))?;
+        println!(
+            "{}: offset {}, {} bytes{}",
+            file,
+            carved.offset,
+            carved.len,
+            if carved.damage.is_empty() {
+                ""
+            } else {
+                ", partial"
+            }
+        );
+        for damage in &carved.damage {
+            println!("  Damage: {}", damage);
+        }
+        files.push(file);
+    }
+    for offset in &carving.skipped {
+        println!("Skipped incomplete png at offset {}", offset);
+    }
+    fs::write(
+        out.join("manifest.json"),
+        serde_json::to_string_pretty(&carving.manifest(files))?,
+    )?;
+    println!(
+        "Extracted {} png files to {}",
+        carving.images.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+pub(crate) fn make_polyglot(
+    file_path: PathBuf,
+    zip_path: PathBuf,
+    output_file: Option<PathBuf>,
+) -> Result<()> {
+    let file_bytes = read_to_bytes(&file_path)?;
+    let mut png = Png::try_from(file_bytes.as_ref())?;
+    if !png.trailer().is_empty() {
+        bail!("The png file already has data after IEND");
+    }
+    let zip_bytes = read_to_bytes(zip_path)?;
+    let shift = png.as_bytes().len();
+    png.set_trailer(zip::relocate(&zip_bytes, shift)?);
+    write_png(&png, output_file.unwrap_or(file_path))?;
+    println!("Polyglot created successfully");
+    Ok(())
+}
+
+pub(crate) fn watermark_png(
+    file_path: PathBuf,
+    output_file: Option<PathBuf>,
+    key: String,
+    keep_message: Vec<String>,
+) -> Result<()> {
+    let keep = chunk_types(&keep_message, None)?;
+    let file_bytes = read_to_bytes(&file_path)?;
+    let mut png = Png::try_from(file_bytes.as_ref())?;
+    let changed = embed_watermark(&mut png, &key, &keep)?;
+    write_png(&png, output_file.unwrap_or(file_path))?;
+    println!("Changed {} samples", changed);
+    println!("Watermark embedded successfully");
+    Ok(())
+}
+
+fn embed_watermark(png: &mut Png, key: &str, keep: &[ChunkType]) -> Result<usize> {
+    let mut image = Image::try_from(&*png)?;
+    let changed = watermark::embed(&mut image, key)?;
+    image.write_to(png)?;
+    rewrite_unsafe_to_copy(png, keep);
+    Ok(changed)
+}
+
+pub(crate) fn verify_watermark(
+    file_path: PathBuf,
+    key: String,
+    mask: Option<PathBuf>,
+) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let png = Png::try_from(file_bytes.as_ref())?;
+    let image = Image::try_from(&png)?;
+    let map = watermark::verify(&image, &key)?;
+    println!("{}", map);
+    if let Some(mask) = mask {
+        write_png(&map.mask(&image.ihdr).to_png()?, mask)?;
+    }
+    if map.modified_blocks() > 0 {
+        bail!("Watermark verification failed");
+    }
+    Ok(())
+}
+
+pub(crate) fn mark_png(
+    file_path: PathBuf,
+    id: String,
+    output_file: Option<PathBuf>,
+    key: String,
+    strength: u8,
+    keep_message: Vec<String>,
+) -> Result<()> {
+    let id = u64::from_str_radix(id.trim_start_matches("0x"), 16)
+        .with_context(|| "ID must be at most 16 hexadecimal digits")?;
+    let keep = chunk_types(&keep_message, None)?;
+    let file_bytes = read_to_bytes(&file_path)?;
+    let mut png = Png::try_from(file_bytes.as_ref())?;
+    let mut image = Image::try_from(&png)?;
+    let changed = spread_spectrum::embed(&mut image, &key, id, strength)?;
+    image.write_to(&mut png)?;
+    rewrite_unsafe_to_copy(&mut png, &keep);
+    write_png(&png, output_file.unwrap_or(file_path))?;
+    println!("Changed {} samples", changed);
+    println!("Mark {:016x} embedded successfully", id);
+    Ok(())
+}
+
+pub(crate) fn detect_mark(file_path: PathBuf, key: String) -> Result<()> {
+    let file_bytes = read_to_bytes(file_path)?;
+    let png = Png::try_from(file_bytes.as_ref())?;
+    let image = Image::try_from(&png)?;
+    let palette = Palette::try_from(&png).ok();
+    let detection = spread_spectrum::detect(&image, palette.as_ref(), &key)?;
+    println!("ID: {:016x}", detection.id);
+    println!("Confidence: {:.4}", detection.confidence);
+    Ok(())
+}
+
+// 各方法可嵌入的消息字节数，已扣除各自的头部开销；None 表示没有实际限制
+// chunk 方法受块长度字段限制，最多 2^31 - 1 字节
+fn method_capacity(
+    png: &Png,
+    method: Method,
+    key: &str,
+    near_opaque: bool,
+) -> Result<Option<usize>> {
+    let capacity = match method {
+        Method::Chunk => i32::MAX as usize,
+        Method::Zlib | Method::Trailer => return Ok(None),
+        // 最坏情况下每个字节需要 256 字节的图像数据
+        Method::IdatSplit => (png.image_data().len() / 256).saturating_sub(PAYLOAD_OVERHEAD),
+        Method::ChunkOrder => chunk_order::capacity(png),
+        Method::Matrix => matrix::capacity(&Image::try_from(png)?)?,
+        Method::Palette => palette::capacity(&Image::try_from(png)?, &Palette::try_from(png)?)?,
+        Method::Alpha => alpha::capacity(png, &Image::try_from(png)?, key, near_opaque)?,
+        Method::FilterType => filter_type::capacity(&Image::try_from(png)?),
+        Method::Reversible => reversible::capacity(&Image::try_from(png)?, key)?,
+    };
+    Ok(Some(capacity))
+}
+
+fn embed_in_pixels(png: &mut Png, chunk: &Chunk, method: &MethodArgs) -> Result<usize> {
+    let key = method.key()?;
+    let mut image = Image::try_from(&*png)?;
+    let changed = match method.method {
+        Method::Matrix => matrix::embed(&mut image, key, chunk)?,
+        Method::Reversible => reversible::embed(&mut image, key, chunk)?,
+        Method::Palette => palette::embed(&mut image, &Palette::try_from(&*png)?, key, chunk)?,
+        Method::Alpha => {
+            let capacity = alpha::capacity(png, &image, key, method.near_opaque)?;
+            println!("Capacity: {} bytes", capacity);
+            alpha::embed(png, &mut image, key, method.near_opaque, chunk)?
+        }
+        Method::FilterType => {
+            // 只改变每行的过滤类型，像素保持不变
+            let filters = filter_type::embed(&image, key, chunk)?;
+            png.set_image_data(image.compress_with(&filters))?;
+            return Ok(0);
+        }
+        Method::Chunk | Method::IdatSplit | Method::ChunkOrder | Method::Zlib | Method::Trailer => {
+            unreachable!()
+        }
+    };
+    image.write_to(png)?;
+    Ok(changed)
+}
+
+// --keep-message 给出的块类型，encode 和 decode 还包括命令本身的块类型
+fn chunk_types(keep_message: &[String], chunk_type: Option<&str>) -> Result<Vec<ChunkType>> {
+    keep_message
+        .iter()
+        .map(String::as_str)
+        .chain(chunk_type)
+        .map(ChunkType::from_str)
+        .collect()
+}
+
+/*
+修改了 IDAT 或 PLTE 之后处理复制不安全的未知块：
+keep 中的消息块类型由本工具（encode 的 chunk 方法）写入，与像素无关，重新写入文件；
+其他块的含义未知，即使内容看起来是文本也只能删除并给出警告。返回被删除的块。
+ */
+fn rewrite_unsafe_to_copy(png: &mut Png, keep: &[ChunkType]) -> Vec<Chunk> {
+    let mut dropped = Vec::new();
+    for chunk in png.remove_unsafe_to_copy() {
+        if keep.contains(chunk.chunk_type()) {
+            println!("Re-embedded unsafe-to-copy message {}", chunk.chunk_type());
+            png.append_chunk(chunk);
+        } else {
+            println!(
+                "Warning: dropped unsafe-to-copy chunk {} ({} bytes)",
+                chunk.chunk_type(),
+                chunk.data().len()
+            );
+            dropped.push(chunk);
+        }
+    }
+    dropped
+}
+
+fn extract_from_pixels(png: &Png, method: &MethodArgs) -> Result<Chunk> {
+    let key = method.key()?;
+    let image = Image::try_from(png)?;
+    match method.method {
+        Method::Matrix => matrix::extract(&image, key),
+        Method::Reversible => reversible::extract(&image, key),
+        Method::Palette => palette::extract(&image, &Palette::try_from(png)?, key),
+        Method::Alpha => alpha::extract(png, &image, key, method.near_opaque),
+        Method::FilterType => filter_type::extract(&image, key),
+        Method::Chunk | Method::IdatSplit | Method::ChunkOrder | Method::Zlib | Method::Trailer => {
+            unreachable!()
+        }
+    }
+}
+
+// lenient 时跳过损坏的部分，并输出损坏记录
+fn parse_png(file_bytes: &[u8], lenient: bool) -> Result<Png> {
+    if !lenient {
+        return Png::try_from(file_bytes);
+    }
+    let recovered = recovery::recover(file_bytes);
+    for damage in &recovered.damage {
+        println!("Damage: {}", damage);
+    }
+    Ok(recovered.png)
+}
+
 fn read_to_bytes<P: AsRef<Path>>(file_path: P) -> Result<Vec<u8>> {
     let mut rf = File::open(&file_path)?;
     let mut file_bytes = Vec::new();
@@ -76,18 +683,76 @@ fn read_to_bytes<P: AsRef<Path>>(file_path: P) -> Result<Vec<u8>> {
 }
 
 // 安全修改文件内容（如果是就地修改文件，程序运行时断电或突然终止，可能会损坏文件内容）
-fn safe_overwrite() -> Result<()> {
-    // 读取原文件的内容
-    let mut rf = File::open("foo.txt")?;
-    let mut file_bytes = vec![];
-    rf.read_to_end(&mut file_bytes)?;
-    drop(rf); // 显式删除文件对象，避免无法覆盖
-
-    file_bytes.remove(0);
-    // 创建临时文件
-    let mut temp_file = File::create("foo_new.txt")?;
-    temp_file.write_all(&file_bytes)?;
-    // 使用临时文件替换原文件
-    fs::rename("foo_new.txt", "foo.txt")?;
+// 先写入临时文件，再用临时文件替换原文件
+fn write_png<P: AsRef<Path>>(png: &Png, file_path: P) -> Result<()> {
+    let file_path = file_path.as_ref();
+    let temp_p = file_path.with_extension("png.temp");
+    let mut temp_f = File::create(&temp_p)?;
+    temp_f.write_all(png.as_bytes().as_ref())?;
+    drop(temp_f); // 显式关闭文件，避免无法替换
+    fs::rename(temp_p, file_path)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+
+    fn testing_png() -> Png {
+        let mut png = testing_image(8, 8, 2, 8).to_png().unwrap();
+        for (chunk_type, data) in [("ruST", &b"secret"[..]), ("abCD", b""), ("xyZW", b"\xff")] {
+            png.append_chunk(Chunk::new(
+                ChunkType::from_str(chunk_type).unwrap(),
+                data.to_vec(),
+            ));
+        }
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rewrite_keeps_own_message() {
+        let mut png = testing_png();
+        let keep = chunk_types(&[], Some("ruST")).unwrap();
+        let dropped = rewrite_unsafe_to_copy(&mut png, &keep);
+        assert_eq!(types(&png), ["IHDR", "IDAT", "ruST", "IEND"]);
+        assert_eq!(png.chunk_by_type("ruST").unwrap().data(), b"secret");
+        let dropped: Vec<String> = dropped.iter().map(|x| x.chunk_type().to_string()).collect();
+        assert_eq!(dropped, ["abCD", "xyZW"]);
+    }
+
+    #[test]
+    fn test_rewrite_drops_foreign_chunks() {
+        // 内容是合法的 UTF-8（包括空块）也不能保留
+        let mut png = testing_png();
+        let dropped = rewrite_unsafe_to_copy(&mut png, &[]);
+        assert_eq!(types(&png), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(dropped.len(), 3);
+    }
+
+    #[test]
+    fn test_watermark_keeps_message() {
+        // encode 用 chunk 方法写入的 ruST 消息在加水印后仍然保留
+        let mut png = testing_png();
+        let keep = chunk_types(&["ruST".to_string()], None).unwrap();
+        embed_watermark(&mut png, "key", &keep).unwrap();
+        assert_eq!(types(&png), ["IHDR", "IDAT", "ruST", "IEND"]);
+        assert_eq!(png.chunk_by_type("ruST").unwrap().data(), b"secret");
+        let image = Image::try_from(&png).unwrap();
+        assert_eq!(
+            watermark::verify(&image, "key").unwrap().modified_blocks(),
+            0
+        );
+
+        let mut png = testing_png();
+        embed_watermark(&mut png, "key", &[]).unwrap();
+        assert_eq!(types(&png), ["IHDR", "IDAT", "IEND"]);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::stego::{bytes_to_bits, KeyRng, PayloadReader, PAYLOAD_OVERHEAD};
+use anyhow::{bail, Result};
+
+/*
+每行扫描线的过滤类型可以任意选择，不影响解码后的像素，只影响压缩后的数据。
+对每一行，代价（见 Image::filter_cost）接近最小值的过滤类型都是候选，
+从 c 个候选中选择第几个即可携带 floor(log2(c)) 个比特。
+像素不变，解码时可以重新计算出完全相同的候选集合。
+ */
+fn candidates(image: &Image, y: usize) -> Vec<u8> {
+    let costs: Vec<u64> = (0..5).map(|filter| image.filter_cost(y, filter)).collect();
+    let min_cost = costs.iter().copied().min().unwrap_or(0);
+    // 允许比最小代价高 1/4，保证压缩率不会明显变差
+    let limit = min_cost + min_cost / 4 + 1;
+    (0..5)
+        .filter(|&filter| costs[filter as usize] <= limit)
+        .collect()
+}
+
+fn bits_per_row(candidates: &[u8]) -> usize {
+    candidates.len().ilog2() as usize
+}
+
+fn keyed_rows(image: &Image, key: &str) -> Vec<usize> {
+    let mut rows: Vec<usize> = (0..image.ihdr.height as usize).collect();
+    KeyRng::new(key).shuffle(&mut rows);
+    rows
+}
+
+// 可嵌入的消息字节数（已扣除长度、块类型和 CRC）
+pub(crate) fn capacity(image: &Image) -> usize {
+    let bits: usize = (0..image.ihdr.height as usize)
+        .map(|y| bits_per_row(&candidates(image, y)))
+        .sum();
+    (bits / 8).saturating_sub(PAYLOAD_OVERHEAD)
+}
+
+// 返回携带消息的每行过滤类型，像素数据保持不变
+pub(crate) fn embed(image: &Image, key: &str, chunk: &Chunk) -> Result<Vec<u8>> {
+    let bits = bytes_to_bits(&chunk.as_bytes());
+    let mut filters = image.adaptive_filters();
+    let mut rest = &bits[..];
+    for y in keyed_rows(image, key) {
+        if rest.is_empty() {
+            break;
+        }
+        let candidates = candidates(image, y);
+        let (row_bits, remain) = rest.split_at(bits_per_row(&candidates).min(rest.len()));
+        let value = row_bits
+            .iter()
+            .fold(0, |acc, &bit| (acc << 1) | bit as usize)
+            << (bits_per_row(&candidates) - row_bits.len());
+        filters[y] = candidates[value];
+        rest = remain;
+    }
+    if !rest.is_empty() {
+        bail!(
+            "Message too large: needs {} bytes, only {} available",
+            chunk.data().len(),
+            capacity(image)
+        );
+    }
+    Ok(filters)
+}
+
+pub(crate) fn extract(image: &Image, key: &str) -> Result<Chunk> {
+    let mut reader = PayloadReader::default();
+    for y in keyed_rows(image, key) {
+        let candidates = candidates(image, y);
+        let bits = bits_per_row(&candidates);
+        let value = candidates
+            .iter()
+            .position(|&x| x == image.filters[y])
+            .unwrap_or(0);
+        if let Some(chunk) = reader.push((0..bits).rev().map(|i| ((value >> i) & 1) as u8))? {
+            return Ok(chunk);
+        }
+    }
+    bail!("No embedded message found (wrong key?)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_filter_type_round_trip() {
+        let image = testing_image(32, 512, 2, 8);
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"rows".to_vec());
+        let filters = embed(&image, "key", &chunk).unwrap();
+
+        let mut png = image.to_png().unwrap();
+        png.set_image_data(image.compress_with(&filters)).unwrap();
+        let decoded = Image::try_from(&png).unwrap();
+        // 像素完全相同
+        assert_eq!(decoded.data, image.data);
+        assert_eq!(decoded.filters, filters);
+
+        let actual = extract(&decoded, "key").unwrap();
+        assert_eq!(actual.data_as_string().unwrap(), "rows");
+    }
+
+    #[test]
+    fn test_filter_type_message_too_large() {
+        let image = testing_image(8, 8, 2, 8);
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"too long".to_vec());
+        assert!(embed(&image, "key", &chunk).is_err());
+    }
+}
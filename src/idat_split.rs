@@ -0,0 +1,99 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::stego::{bytes_to_bits, PayloadReader};
+use anyhow::{bail, Result};
+
+/*
+编码器通常会在任意位置拆分 IDAT，拆分方式不影响解压后的数据。
+将原有的压缩数据流重新拆分，每个 IDAT 块的长度减 1 就是消息的一个字节（长度为 1 到 256），
+剩余的数据全部放在最后一个 IDAT 块中。像素和块类型都不发生变化。
+ */
+pub(crate) fn embed(png: &mut Png, chunk: &Chunk) -> Result<()> {
+    let stream = png.image_data();
+    let payload = chunk.as_bytes();
+    let needed: usize = payload.iter().map(|&x| x as usize + 1).sum();
+    if needed > stream.len() {
+        bail!(
+            "Message too large: needs {} bytes of image data, only {} available",
+            needed,
+            stream.len()
+        );
+    }
+
+    let mut parts = Vec::with_capacity(payload.len() + 1);
+    let mut rest = &stream[..];
+    for &byte in &payload {
+        let (part, remain) = rest.split_at(byte as usize + 1);
+        parts.push(part.to_vec());
+        rest = remain;
+    }
+    if !rest.is_empty() {
+        parts.push(rest.to_vec());
+    }
+    png.set_image_chunks(parts)
+}
+
+pub(crate) fn extract(png: &Png) -> Result<Chunk> {
+    let mut reader = PayloadReader::default();
+    for chunk in png
+        .chunks()
+        .iter()
+        .filter(|x| x.chunk_type().bytes() == Png::IDAT)
+    {
+        if chunk.length() == 0 || chunk.length() > 256 {
+            break;
+        }
+        if let Some(chunk) = reader.push(bytes_to_bits(&[(chunk.length() - 1) as u8]))? {
+            return Ok(chunk);
+        }
+    }
+    bail!("No embedded message found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use crate::stego::KeyRng;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_idat_split_round_trip() {
+        // 随机像素几乎无法压缩，保证数据流足够长
+        let mut image = testing_image(32, 32, 6, 8);
+        let mut rng = KeyRng::new("noise");
+        image
+            .data
+            .iter_mut()
+            .for_each(|x| *x = rng.next_u64() as u8);
+        let mut png = image.to_png().unwrap();
+        let stream = png.image_data();
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"split".to_vec());
+        embed(&mut png, &chunk).unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        // 压缩数据流和像素都没有变化
+        assert_eq!(png.image_data(), stream);
+        assert_eq!(Image::try_from(&png).unwrap().data, image.data);
+
+        let actual = extract(&png).unwrap();
+        assert_eq!(actual.chunk_type().to_string(), "ruSt");
+        assert_eq!(actual.data_as_string().unwrap(), "split");
+    }
+
+    #[test]
+    fn test_idat_split_message_too_large() {
+        let image = testing_image(4, 4, 2, 8);
+        let mut png = image.to_png().unwrap();
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![255; 64]);
+        assert!(embed(&mut png, &chunk).is_err());
+    }
+
+    #[test]
+    fn test_idat_split_nothing_embedded() {
+        let png = testing_image(64, 64, 2, 8).to_png().unwrap();
+        assert!(extract(&png).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::palette::Palette;
+use crate::png::Png;
+use anyhow::{bail, Context, Error, Result};
+use miniz_oxide::inflate::TINFLStatus;
+
+/*
+IHDR：宽度(4) + 高度(4) + 位深度(1) + 颜色类型(1) + 压缩方法(1) + 过滤方法(1) + 隔行扫描方法(1)
+
+颜色类型：0 = 灰度，2 = 真彩色，3 = 索引色，4 = 带 alpha 的灰度，6 = 带 alpha 的真彩色
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ihdr {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) bit_depth: u8,
+    pub(crate) color_type: u8,
+    pub(crate) interlace: u8,
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(value: &Chunk) -> Result<Self, Self::Error> {
+        if value.chunk_type().bytes() != Png::IHDR {
+            bail!("Not an IHDR chunk");
+        }
+        let data = value.data();
+        if data.len() != 13 {
+            bail!("IHDR data length must be 13");
+        }
+        let ihdr = Self {
+            width: u32::from_be_bytes(data[0..4].try_into()?),
+            height: u32::from_be_bytes(data[4..8].try_into()?),
+            bit_depth: data[8],
+            color_type: data[9],
+            interlace: data[12],
+        };
+        let allowed_depths: &[u8] = match ihdr.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => bail!("Invalid color type {}", ihdr.color_type),
+        };
+        if !allowed_depths.contains(&ihdr.bit_depth) {
+            bail!(
+                "Invalid bit depth {} for color type {}",
+                ihdr.bit_depth,
+                ihdr.color_type
+            );
+        }
+        if ihdr.width == 0 || ihdr.height == 0 {
+            bail!("Image dimensions must be non-zero");
+        }
+        Ok(ihdr)
+    }
+}
+
+impl Ihdr {
+    pub(crate) fn to_chunk(self) -> Result<Chunk> {
+        let data: Vec<u8> = self
+            .width
+            .to_be_bytes()
+            .iter()
+            .chain(self.height.to_be_bytes().iter())
+            .chain([self.bit_depth, self.color_type, 0, 0, self.interlace].iter())
+            .copied()
+            .collect();
+        Ok(Chunk::new(ChunkType::try_from(Png::IHDR)?, data))
+    }
+
+    pub(crate) fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn has_alpha(&self) -> bool {
+        matches!(self.color_type, 4 | 6)
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // 过滤器以字节为单位，位深度小于 8 时按 1 个字节计算
+    pub(crate) fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    // 每行扫描线的字节数（不含过滤类型字节）
+    pub(crate) fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+/*
+去除过滤后的像素数据，按行连续存放，不包含每行开头的过滤类型字节。
+filters 记录每行原本使用的过滤类型。
+ */
+#[derive(Debug, Clone)]
+pub(crate) struct Image {
+    pub(crate) ihdr: Ihdr,
+    pub(crate) data: Vec<u8>,
+    pub(crate) filters: Vec<u8>,
+}
+
+impl TryFrom<&Png> for Image {
+    type Error = Error;
+
+    fn try_from(value: &Png) -> Result<Self, Self::Error> {
+        let ihdr_chunk = value
+            .chunks()
+            .first()
+            .filter(|x| x.chunk_type().bytes() == Png::IHDR)
+            .with_context(|| "IHDR must be the first chunk")?;
+        let ihdr = Ihdr::try_from(ihdr_chunk)?;
+        if ihdr.interlace != 0 {
+            bail!("Interlaced images are not supported");
+        }
+
+        let stride = ihdr.stride();
+        let height = ihdr.height as usize;
+        let expected = (stride + 1)
+            .checked_mul(height)
+            .with_context(|| "Image dimensions are too large")?;
+        // 只解压 IHDR 描述的数据量，多余的数据忽略
+        let raw = match miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+            &value.image_data(),
+            expected,
+        ) {
+            Ok(raw) => raw,
+            Err(e) if e.status == TINFLStatus::HasMoreOutput => e.output,
+            Err(e) => bail!("Invalid image data: {:?}", e.status),
+        };
+        if raw.len() < expected {
+            bail!("Image data is shorter than IHDR describes");
+        }
+
+        let bpp = ihdr.bytes_per_pixel();
+        let zeros = vec![0; stride];
+        let mut data = vec![0; stride * height];
+        let mut filters = Vec::with_capacity(height);
+        for y in 0..height {
+            let line = &raw[y * (stride + 1)..(y + 1) * (stride + 1)];
+            let (done, rest) = data.split_at_mut(y * stride);
+            let previous = if y == 0 {
+                &zeros[..]
+            } else {
+                &done[(y - 1) * stride..]
+            };
+            let current = &mut rest[..stride];
+            current.copy_from_slice(&line[1..]);
+            unfilter_row(line[0], current, previous, bpp)?;
+            filters.push(line[0]);
+        }
+
+        Ok(Self {
+            ihdr,
+            data,
+            filters,
+        })
+    }
+}
+
+impl Image {
+    pub(crate) fn row(&self, y: usize) -> &[u8] {
+        let stride = self.ihdr.stride();
+        &self.data[y * stride..(y + 1) * stride]
+    }
+
+    // 单通道图像（索引色或灰度）中像素值在 data 中的字节位置、右移位数和掩码
+    fn locate(&self, pixel: usize) -> (usize, usize, u8) {
+        let width = self.ihdr.width as usize;
+        let depth = self.ihdr.bit_depth as usize;
+        let bit = (pixel % width) * depth;
+        let byte = (pixel / width) * self.ihdr.stride() + bit / 8;
+        (byte, 8 - depth - bit % 8, (0xffu16 >> (8 - depth)) as u8)
+    }
+
+    pub(crate) fn index(&self, pixel: usize) -> u8 {
+        let (byte, shift, mask) = self.locate(pixel);
+        (self.data[byte] >> shift) & mask
+    }
+
+    pub(crate) fn set_index(&mut self, pixel: usize, value: u8) {
+        let (byte, shift, mask) = self.locate(pixel);
+        self.data[byte] = (self.data[byte] & !(mask << shift)) | ((value & mask) << shift);
+    }
+
+    // 任意位深度下像素某个通道的样本值（索引色图像返回调色板索引）
+    pub(crate) fn sample(&self, pixel: usize, channel: usize) -> u16 {
+        match self.ihdr.bit_depth {
+            1 | 2 | 4 => self.index(pixel) as u16,
+            8 => self.data[pixel * self.ihdr.channels() + channel] as u16,
+            _ => {
+                let start = (pixel * self.ihdr.channels() + channel) * 2;
+                u16::from_be_bytes([self.data[start], self.data[start + 1]])
+            }
+        }
+    }
+
+    pub(crate) fn set_sample(&mut self, pixel: usize, channel: usize, value: u16) {
+        match self.ihdr.bit_depth {
+            1 | 2 | 4 => self.set_index(pixel, value as u8),
+            8 => self.data[pixel * self.ihdr.channels() + channel] = value as u8,
+            _ => {
+                let start = (pixel * self.ihdr.channels() + channel) * 2;
+                self.data[start..start + 2].copy_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+
+    pub(crate) fn max_sample(&self) -> u16 {
+        ((1u32 << self.ihdr.bit_depth) - 1) as u16
+    }
+
+    /*
+    可以修改最低有效位的字节在 data 中的位置：
+    只使用颜色通道（不修改 alpha），16 位深度时使用每个样本的低字节。
+    索引色和低于 8 位的灰度图像不能直接修改最低位。
+     */
+    pub(crate) fn lsb_positions(&self) -> Result<Vec<usize>> {
+        if self.ihdr.color_type == 3 || self.ihdr.bit_depth < 8 {
+            bail!("Pixel embedding requires 8 or 16 bit grayscale or truecolor images");
+        }
+        let sample_bytes = self.ihdr.bit_depth as usize / 8;
+        let channels = self.ihdr.channels();
+        let color_channels = if self.ihdr.has_alpha() {
+            channels - 1
+        } else {
+            channels
+        };
+        let pixels = self.ihdr.width as usize * self.ihdr.height as usize;
+        let mut positions = Vec::with_capacity(pixels * color_channels);
+        for pixel in 0..pixels {
+            for channel in 0..color_channels {
+                positions.push((pixel * channels + channel) * sample_bytes + sample_bytes - 1);
+            }
+        }
+        Ok(positions)
+    }
+
+    // 每行选用绝对差之和最小的过滤器（PNG 规范推荐的启发式方法），索引色和低位深度不过滤
+    pub(crate) fn adaptive_filters(&self) -> Vec<u8> {
+        let height = self.ihdr.height as usize;
+        if self.ihdr.color_type == 3 || self.ihdr.bit_depth < 8 {
+            return vec![0; height];
+        }
+        (0..height)
+            .map(|y| {
+                (0..5)
+                    .min_by_key(|&filter| self.filter_cost(y, filter))
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    // 过滤后的字节视为有符号数时的绝对值之和，越小通常压缩效果越好
+    pub(crate) fn filter_cost(&self, y: usize, filter: u8) -> u64 {
+        self.filtered_row(y, filter)
+            .iter()
+            .map(|&x| (x as i8).unsigned_abs() as u64)
+            .sum()
+    }
+
+    pub(crate) fn filtered_row(&self, y: usize, filter: u8) -> Vec<u8> {
+        let stride = self.ihdr.stride();
+        let zeros = vec![0; stride];
+        let previous = if y == 0 { &zeros[..] } else { self.row(y - 1) };
+        filter_row(filter, self.row(y), previous, self.ihdr.bytes_per_pixel())
+    }
+
+    // 使用给定的每行过滤类型生成压缩前的数据流
+    pub(crate) fn raw_bytes(&self, filters: &[u8]) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.ihdr.stride() + 1) * filters.len());
+        for (y, &filter) in filters.iter().enumerate() {
+            raw.push(filter);
+            raw.extend(self.filtered_row(y, filter));
+        }
+        raw
+    }
+
+    pub(crate) fn compress(&self) -> Vec<u8> {
+        self.compress_with(&self.adaptive_filters())
+    }
+
+    pub(crate) fn compress_with(&self, filters: &[u8]) -> Vec<u8> {
+        miniz_oxide::deflate::compress_to_vec_zlib(&self.raw_bytes(filters), 9)
+    }
+
+    // 只包含 IHDR、IDAT、IEND 的新 PNG
+    pub(crate) fn to_png(&self) -> Result<Png> {
+        Ok(Png::from_chunks(vec![
+            self.ihdr.to_chunk()?,
+            Chunk::new(ChunkType::try_from(Png::IDAT)?, self.compress()),
+            Chunk::new(ChunkType::try_from(Png::IEND)?, Vec::new()),
+        ]))
+    }
+
+    // 将像素数据重新编码到 png 的 IDAT 块中
+    pub(crate) fn write_to(&self, png: &mut Png) -> Result<()> {
+        png.set_image_data(self.compress())
+    }
+}
+
+/*
+按通道拆开的样本值，索引色图像按调色板展开为 RGB。
+比较和分析图像时使用，不关心原来的颜色类型和位深度。
+ */
+pub(crate) struct Planes {
+    pub(crate) names: Vec<&'static str>,
+    pub(crate) max: u16,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) planes: Vec<Vec<u16>>,
+}
+
+impl Planes {
+    pub(crate) fn new(image: &Image, palette: Option<&Palette>) -> Result<Planes> {
+        let ihdr = image.ihdr;
+        let pixels = ihdr.width as usize * ihdr.height as usize;
+        let (names, max, planes) = if ihdr.color_type == 3 {
+            let palette = palette.with_context(|| "No PLTE chunk")?;
+            let colors: Vec<[u8; 3]> = (0..pixels)
+                .map(|pixel| palette.rgb(image.index(pixel)))
+                .collect();
+            let planes = (0..3)
+                .map(|channel| colors.iter().map(|x| x[channel] as u16).collect())
+                .collect();
+            (vec!["R", "G", "B"], 255, planes)
+        } else {
+            let names = match ihdr.color_type {
+                0 => vec!["Gray"],
+                2 => vec!["R", "G", "B"],
+                4 => vec!["Gray", "A"],
+                _ => vec!["R", "G", "B", "A"],
+            };
+            let planes = (0..ihdr.channels())
+                .map(|channel| (0..pixels).map(|x| image.sample(x, channel)).collect())
+                .collect();
+            (names, image.max_sample(), planes)
+        };
+        Ok(Self {
+            names,
+            max,
+            width: ihdr.width as usize,
+            height: ihdr.height as usize,
+            planes,
+        })
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// 过滤类型：0 = None，1 = Sub，2 = Up，3 = Average，4 = Paeth
+fn filter_row(filter: u8, row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    (0..row.len())
+        .map(|i| {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = previous[i];
+            let c = if i >= bpp { previous[i - bpp] } else { 0 };
+            let predictor = match filter {
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => 0,
+            };
+            row[i].wrapping_sub(predictor)
+        })
+        .collect()
+}
+
+fn unfilter_row(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<()> {
+    if filter > 4 {
+        bail!("Invalid filter type {}", filter);
+    }
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        let predictor = match filter {
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => 0,
+        };
+        row[i] = row[i].wrapping_add(predictor);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // 生成一张内容可预测的测试图像
+    pub(crate) fn testing_image(width: u32, height: u32, color_type: u8, bit_depth: u8) -> Image {
+        let ihdr = Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlace: 0,
+        };
+        let data = (0..ihdr.stride() * height as usize)
+            .map(|i| ((i * 7 + i / 13) % 251) as u8)
+            .collect();
+        Image {
+            ihdr,
+            data,
+            filters: vec![0; height as usize],
+        }
+    }
+
+    #[test]
+    fn test_ihdr_from_chunk() {
+        let ihdr = testing_image(50, 40, 6, 8).ihdr;
+        let actual = Ihdr::try_from(&ihdr.to_chunk().unwrap()).unwrap();
+        assert_eq!(actual, ihdr);
+        assert_eq!(actual.stride(), 200);
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        let mut ihdr = testing_image(5, 5, 2, 8).ihdr;
+        ihdr.bit_depth = 4;
+        assert!(Ihdr::try_from(&ihdr.to_chunk().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_image_round_trip() {
+        for (color_type, bit_depth) in [(0, 1), (0, 16), (2, 8), (3, 4), (4, 8), (6, 16)] {
+            let image = testing_image(13, 7, color_type, bit_depth);
+            let png = image.to_png().unwrap();
+            let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+            let decoded = Image::try_from(&png).unwrap();
+            assert_eq!(decoded.data, image.data);
+        }
+    }
+
+    #[test]
+    fn test_all_filters_round_trip() {
+        let image = testing_image(9, 6, 2, 8);
+        for filter in 0..5 {
+            let mut png = image.to_png().unwrap();
+            png.set_image_data(image.compress_with(&[filter; 6]))
+                .unwrap();
+            let decoded = Image::try_from(&png).unwrap();
+            assert_eq!(decoded.data, image.data);
+            assert_eq!(decoded.filters, vec![filter; 6]);
+        }
+    }
+
+    #[test]
+    fn test_untrusted_dimensions() {
+        // 尺寸乘积溢出
+        let mut png = testing_image(2, 2, 6, 16).to_png().unwrap();
+        let mut ihdr = Ihdr::try_from(&png.chunks()[0]).unwrap();
+        ihdr.width = u32::MAX;
+        ihdr.height = u32::MAX;
+        png.chunks_mut()[0] = ihdr.to_chunk().unwrap();
+        assert!(Image::try_from(&png).is_err());
+
+        // 解压后的数据比 IHDR 描述的多得多时只解压需要的部分
+        let image = testing_image(4, 4, 0, 8);
+        let mut png = image.to_png().unwrap();
+        png.set_image_data(miniz_oxide::deflate::compress_to_vec_zlib(
+            &vec![0; 1 << 20],
+            6,
+        ))
+        .unwrap();
+        let decoded = Image::try_from(&png).unwrap();
+        assert_eq!(decoded.data, vec![0; 16]);
+    }
+
+    #[test]
+    fn test_packed_index() {
+        let mut image = testing_image(5, 3, 3, 2);
+        image.set_index(7, 3);
+        image.set_index(8, 0);
+        assert_eq!(image.index(7), 3);
+        assert_eq!(image.index(8), 0);
+
+        let data = image.data.clone();
+        for pixel in 0..15 {
+            let value = image.index(pixel);
+            image.set_index(pixel, value);
+        }
+        assert_eq!(image.data, data);
+    }
+
+    #[test]
+    fn test_lsb_positions_skip_alpha() {
+        let image = testing_image(2, 1, 6, 16);
+        assert_eq!(image.lsb_positions().unwrap(), vec![1, 3, 5, 9, 11, 13]);
+        assert!(testing_image(2, 2, 3, 8).lsb_positions().is_err());
+    }
+
+    #[test]
+    fn test_planes_expand_palette() {
+        let mut image = testing_image(2, 1, 3, 2);
+        image.set_index(1, 1);
+        let plte = Chunk::new(Png::PLTE.try_into().unwrap(), vec![0, 0, 0, 10, 20, 30]);
+        let planes = Planes::new(&image, Some(&Palette::try_from(&plte).unwrap())).unwrap();
+        assert_eq!(planes.names, ["R", "G", "B"]);
+        assert_eq!(planes.planes[2][1], 30);
+
+        let planes = Planes::new(&testing_image(2, 2, 4, 16), None).unwrap();
+        assert_eq!((planes.max, planes.planes.len()), (65535, 2));
+    }
+}
@@ -1,11 +1,41 @@
+mod alpha;
+mod analysis;
+mod anomalies;
 mod args;
+mod bitplanes;
+mod carve;
 mod chunk;
+mod chunk_order;
 mod chunk_type;
 mod commands;
+mod filter_type;
+mod idat_split;
+mod image;
+mod matrix;
+mod metrics;
+mod palette;
 mod png;
+mod policy;
+mod recovery;
+mod repair;
+mod reversible;
+mod scan;
+mod secrets;
+mod spread_spectrum;
+mod stats;
+mod stego;
+mod strip;
+mod trailer;
+mod watermark;
+mod zip;
+mod zlib;
 
 use crate::args::{Args, Commands};
-use crate::commands::{decode_msg, encode_msg, print_msg, remove_msg};
+use crate::commands::{
+    analyze_png, anomaly_report, bit_planes, capacity_report, carve_blob, check_png, compare_png,
+    decode_msg, detect_mark, encode_msg, enforce_policy, make_polyglot, mark_png, print_msg,
+    remove_msg, repair_png, scan_png, scan_secrets, strip_png, verify_watermark, watermark_png,
+};
 use anyhow::Result;
 use clap::Parser;
 
@@ -18,16 +48,129 @@ fn main() -> Result<()> {
             chunk_type,
             message,
             output_file,
-        } => encode_msg(file_path, chunk_type, message, output_file)?,
+            method,
+            keep_message,
+        } => encode_msg(
+            file_path,
+            chunk_type,
+            message,
+            output_file,
+            method,
+            keep_message,
+        )?,
         Commands::Decode {
             file_path,
             chunk_type,
-        } => decode_msg(file_path, chunk_type)?,
+            method,
+            restore,
+            keep_message,
+            lenient,
+        } => decode_msg(
+            file_path,
+            chunk_type,
+            method,
+            restore,
+            keep_message,
+            lenient,
+        )?,
         Commands::Remove {
             file_path,
             chunk_type,
         } => remove_msg(file_path, chunk_type)?,
-        Commands::Print { file_path } => print_msg(file_path)?,
+        Commands::Print { file_path, lenient } => print_msg(file_path, lenient)?,
+        Commands::Check { file_path } => check_png(file_path)?,
+        Commands::Capacity {
+            file_path,
+            key,
+            near_opaque,
+        } => capacity_report(file_path, key, near_opaque)?,
+        Commands::Compare {
+            file_path,
+            other_path,
+            heatmap,
+            min_psnr,
+            min_ssim,
+            max_diff,
+        } => {
+            if !compare_png(file_path, other_path, heatmap, min_psnr, min_ssim, max_diff)? {
+                std::process::exit(2);
+            }
+        }
+        Commands::Analyze { file_path, json } => analyze_png(file_path, json)?,
+        Commands::Scan {
+            file_path,
+            all,
+            extract,
+            output,
+        } => scan_png(file_path, all, extract, output)?,
+        Commands::Bitplanes {
+            file_path,
+            out,
+            diff,
+        } => bit_planes(file_path, out, diff)?,
+        Commands::Anomalies { file_paths } => anomaly_report(file_paths)?,
+        Commands::Secrets {
+            file_path,
+            pattern,
+            json,
+        } => {
+            if !scan_secrets(file_path, pattern, json)? {
+                std::process::exit(2);
+            }
+        }
+        Commands::Strip {
+            file_path,
+            output_file,
+            profile,
+            keep,
+        } => strip_png(file_path, output_file, profile, keep)?,
+        Commands::Enforce {
+            file_path,
+            policy,
+            output_file,
+        } => {
+            if !enforce_policy(file_path, policy, output_file)? {
+                std::process::exit(2);
+            }
+        }
+        Commands::Repair {
+            file_path,
+            output_file,
+        } => {
+            if !repair_png(file_path, output_file)? {
+                std::process::exit(2);
+            }
+        }
+        Commands::Carve {
+            file_path,
+            out,
+            lenient,
+        } => carve_blob(file_path, out, lenient)?,
+        Commands::Polyglot {
+            file_path,
+            zip_path,
+            output_file,
+        } => make_polyglot(file_path, zip_path, output_file)?,
+        Commands::Watermark {
+            file_path,
+            output_file,
+            key,
+            keep_message,
+        } => watermark_png(file_path, output_file, key, keep_message)?,
+        Commands::VerifyWatermark {
+            file_path,
+            key,
+            mask,
+        } => verify_watermark(file_path, key, mask)?,
+        Commands::Mark {
+            file_path,
+            id,
+            output_file,
+            key,
+            strength,
+            keep_message,
+        } => mark_png(file_path, id, output_file, key, strength, keep_message)?,
+        Commands::Detect { file_path, key } => detect_mark(file_path, key)?,
     }
     Ok(())
 }
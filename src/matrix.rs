@@ -0,0 +1,173 @@
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::stego::{bytes_to_bits, KeyRng, PayloadReader, PAYLOAD_OVERHEAD};
+use anyhow::{bail, Context, Result};
+
+/*
+F5 风格的矩阵嵌入（汉明码）：每 n = 2^k - 1 个最低有效位嵌入 k 个比特，最多只需修改其中 1 位。
+嵌入位置由密钥打乱，前 8 个位置直接存放 k，解码时读出 k 就能还原出分组方式。
+k 越大每个比特需要修改的像素越少，但占用的位置越多，编码时根据消息长度和容量取最大的 k。
+ */
+const K_BITS: usize = 8;
+const MAX_K: usize = 16;
+
+fn keyed_positions(image: &Image, key: &str) -> Result<Vec<usize>> {
+    let mut positions = image.lsb_positions()?;
+    KeyRng::new(key).shuffle(&mut positions);
+    Ok(positions)
+}
+
+// 汉明码的校验子：所有最低位为 1 的位置序号（从 1 开始）的异或
+fn syndrome(image: &Image, block: &[usize]) -> usize {
+    block
+        .iter()
+        .enumerate()
+        .filter(|(_, &pos)| image.data[pos] & 1 == 1)
+        .fold(0, |acc, (i, _)| acc ^ (i + 1))
+}
+
+// k = 1 时每个位置嵌入 1 个比特，此时容量最大
+pub(crate) fn capacity(image: &Image) -> Result<usize> {
+    let available = image.lsb_positions()?.len().saturating_sub(K_BITS);
+    Ok((available / 8).saturating_sub(PAYLOAD_OVERHEAD))
+}
+
+// 嵌入消息，返回被修改的样本数
+pub(crate) fn embed(image: &mut Image, key: &str, chunk: &Chunk) -> Result<usize> {
+    let positions = keyed_positions(image, key)?;
+    let bits = bytes_to_bits(&chunk.as_bytes());
+    let available = positions.len().saturating_sub(K_BITS);
+    let k = (1..=MAX_K)
+        .rev()
+        .find(|&k| bits.len().div_ceil(k) * ((1 << k) - 1) <= available)
+        .with_context(|| {
+            format!(
+                "Message too large: needs at least {} samples, only {} available",
+                bits.len(),
+                available
+            )
+        })?;
+
+    let mut changed = 0;
+    for (i, &pos) in positions[..K_BITS].iter().enumerate() {
+        let bit = ((k >> (K_BITS - 1 - i)) & 1) as u8;
+        if image.data[pos] & 1 != bit {
+            image.data[pos] ^= 1;
+            changed += 1;
+        }
+    }
+
+    let n = (1 << k) - 1;
+    for (block, message) in positions[K_BITS..].chunks(n).zip(bits.chunks(k)) {
+        // 最后一组不足 k 位时在低位补 0
+        let message = message
+            .iter()
+            .fold(0, |acc, &bit| (acc << 1) | bit as usize)
+            << (k - message.len());
+        let flip = syndrome(image, block) ^ message;
+        if flip != 0 {
+            image.data[block[flip - 1]] ^= 1;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+pub(crate) fn extract(image: &Image, key: &str) -> Result<Chunk> {
+    let positions = keyed_positions(image, key)?;
+    if positions.len() < K_BITS {
+        bail!("Image is too small");
+    }
+    let k = positions[..K_BITS]
+        .iter()
+        .fold(0, |acc, &pos| (acc << 1) | (image.data[pos] & 1) as usize);
+    if k == 0 || k > MAX_K {
+        bail!("No embedded message found (wrong key?)");
+    }
+
+    let n = (1 << k) - 1;
+    let mut reader = PayloadReader::default();
+    for block in positions[K_BITS..].chunks_exact(n) {
+        let s = syndrome(image, block);
+        if let Some(chunk) = reader.push((0..k).rev().map(|i| ((s >> i) & 1) as u8))? {
+            return Ok(chunk);
+        }
+    }
+    bail!("No embedded message found (wrong key?)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    fn testing_chunk(message: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_matrix_round_trip() {
+        let mut image = testing_image(64, 64, 2, 8);
+        let chunk = testing_chunk("This is a secret message!");
+        embed(&mut image, "key", &chunk).unwrap();
+
+        let actual = extract(&image, "key").unwrap();
+        assert_eq!(actual.chunk_type().to_string(), "ruSt");
+        assert_eq!(
+            actual.data_as_string().unwrap(),
+            "This is a secret message!"
+        );
+    }
+
+    #[test]
+    fn test_matrix_changes_fewer_samples_than_lsb() {
+        let mut image = testing_image(64, 64, 6, 8);
+        let chunk = testing_chunk("short");
+        let bits = chunk.as_bytes().len() * 8;
+        let changed = embed(&mut image, "key", &chunk).unwrap();
+        // 普通 LSB 平均需要修改一半的比特
+        assert!(changed < bits / 4);
+    }
+
+    #[test]
+    fn test_matrix_wrong_key() {
+        let mut image = testing_image(32, 32, 2, 8);
+        embed(&mut image, "key", &testing_chunk("message")).unwrap();
+        assert!(extract(&image, "other key").is_err());
+    }
+
+    #[test]
+    fn test_matrix_capacity() {
+        let image = testing_image(16, 16, 2, 8);
+        let capacity = capacity(&image).unwrap();
+        assert_eq!(capacity, (16 * 16 * 3 - 8) / 8 - PAYLOAD_OVERHEAD);
+        assert!(embed(
+            &mut image.clone(),
+            "key",
+            &testing_chunk(&"x".repeat(capacity))
+        )
+        .is_ok());
+        assert!(embed(
+            &mut image.clone(),
+            "key",
+            &testing_chunk(&"x".repeat(capacity + 1))
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_matrix_message_too_large() {
+        let mut image = testing_image(4, 4, 2, 8);
+        assert!(embed(
+            &mut image,
+            "key",
+            &testing_chunk("This is a secret message!")
+        )
+        .is_err());
+    }
+}
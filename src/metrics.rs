@@ -0,0 +1,242 @@
+use crate::image::{Ihdr, Image, Planes};
+use anyhow::{bail, Result};
+use std::fmt::{Display, Formatter};
+
+/*
+两张图像逐通道比较：
+- PSNR = 10 * log10(MAX^2 / MSE)，完全相同时为无穷大
+- SSIM 在 8x8 窗口（步长 4）上计算后取平均，常数 C1 = (0.01 * MAX)^2，C2 = (0.03 * MAX)^2
+ */
+const WINDOW: usize = 8;
+const STEP: usize = 4;
+
+pub(crate) struct ChannelMetrics {
+    pub(crate) name: &'static str,
+    pub(crate) psnr: f64,
+    pub(crate) ssim: f64,
+    pub(crate) max_diff: u16,
+    pub(crate) changed: usize,
+}
+
+pub(crate) struct Comparison {
+    pub(crate) channels: Vec<ChannelMetrics>,
+    // 至少有一个通道不同的像素数
+    pub(crate) changed_pixels: usize,
+    pub(crate) pixels: usize,
+    // 每个像素所有通道中的最大差值
+    differences: Vec<u16>,
+    width: usize,
+    height: usize,
+}
+
+fn psnr(a: &[u16], b: &[u16], max: u16) -> f64 {
+    let mse = a
+        .iter()
+        .zip(b)
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum::<f64>()
+        / a.len() as f64;
+    10.0 * ((max as f64).powi(2) / mse).log10()
+}
+
+fn window_ssim(a: &[f64], b: &[f64], max: u16) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+    let var_a = a.iter().map(|x| (x - mean_a).powi(2)).sum::<f64>() / n;
+    let var_b = b.iter().map(|x| (x - mean_b).powi(2)).sum::<f64>() / n;
+    let covariance = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (x - mean_a) * (y - mean_b))
+        .sum::<f64>()
+        / n;
+    let c1 = (0.01 * max as f64).powi(2);
+    let c2 = (0.03 * max as f64).powi(2);
+    ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+        / ((mean_a.powi(2) + mean_b.powi(2) + c1) * (var_a + var_b + c2))
+}
+
+fn ssim(a: &[u16], b: &[u16], width: usize, height: usize, max: u16) -> f64 {
+    // 图像小于窗口时整幅图像作为一个窗口
+    let window_width = WINDOW.min(width);
+    let window_height = WINDOW.min(height);
+    let mut total = 0.0;
+    let mut count = 0;
+    for y in (0..=height - window_height).step_by(STEP) {
+        for x in (0..=width - window_width).step_by(STEP) {
+            let pixels = (y..y + window_height)
+                .flat_map(|y| (x..x + window_width).map(move |x| y * width + x));
+            let (wa, wb): (Vec<f64>, Vec<f64>) = pixels.map(|i| (a[i] as f64, b[i] as f64)).unzip();
+            total += window_ssim(&wa, &wb, max);
+            count += 1;
+        }
+    }
+    total / count as f64
+}
+
+impl Comparison {
+    pub(crate) fn new(a: &Planes, b: &Planes) -> Result<Comparison> {
+        if (a.width, a.height) != (b.width, b.height) {
+            bail!(
+                "Image sizes differ: {}x{} and {}x{}",
+                a.width,
+                a.height,
+                b.width,
+                b.height
+            );
+        }
+        if a.names != b.names || a.max != b.max {
+            bail!("Images have different channels or bit depths");
+        }
+        let pixels = a.width * a.height;
+        let mut differences = vec![0; pixels];
+        let channels = a
+            .planes
+            .iter()
+            .zip(&b.planes)
+            .zip(&a.names)
+            .map(|((pa, pb), &name)| {
+                let mut changed = 0;
+                let mut max_diff = 0;
+                for (pixel, (&x, &y)) in pa.iter().zip(pb).enumerate() {
+                    let diff = x.abs_diff(y);
+                    if diff > 0 {
+                        changed += 1;
+                    }
+                    max_diff = max_diff.max(diff);
+                    differences[pixel] = differences[pixel].max(diff);
+                }
+                ChannelMetrics {
+                    name,
+                    psnr: psnr(pa, pb, a.max),
+                    ssim: ssim(pa, pb, a.width, a.height, a.max),
+                    max_diff,
+                    changed,
+                }
+            })
+            .collect();
+        Ok(Self {
+            channels,
+            changed_pixels: differences.iter().filter(|&&x| x > 0).count(),
+            pixels,
+            differences,
+            width: a.width,
+            height: a.height,
+        })
+    }
+
+    pub(crate) fn psnr(&self) -> f64 {
+        self.channels
+            .iter()
+            .map(|x| x.psnr)
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    pub(crate) fn ssim(&self) -> f64 {
+        self.channels.iter().map(|x| x.ssim).fold(1.0, f64::min)
+    }
+
+    pub(crate) fn max_diff(&self) -> u16 {
+        self.channels.iter().map(|x| x.max_diff).max().unwrap_or(0)
+    }
+
+    // 差值热图：黑色表示相同，差值越大越接近红、黄、白，按最大差值归一化
+    pub(crate) fn heatmap(&self) -> Image {
+        let max = self.max_diff().max(1) as f64;
+        let data = self
+            .differences
+            .iter()
+            .flat_map(|&diff| {
+                let t = diff as f64 / max * 3.0;
+                [t, t - 1.0, t - 2.0].map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect();
+        Image {
+            ihdr: Ihdr {
+                width: self.width as u32,
+                height: self.height as u32,
+                bit_depth: 8,
+                color_type: 2,
+                interlace: 0,
+            },
+            data,
+            filters: vec![0; self.height],
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>10} {:>8} {:>8} {:>10}",
+            "Channel", "PSNR (dB)", "SSIM", "Max diff", "Changed"
+        )?;
+        for channel in &self.channels {
+            writeln!(
+                f,
+                "{:<8} {:>10.2} {:>8.5} {:>8} {:>10}",
+                channel.name, channel.psnr, channel.ssim, channel.max_diff, channel.changed
+            )?;
+        }
+        write!(
+            f,
+            "Changed pixels: {} of {} ({:.3}%)",
+            self.changed_pixels,
+            self.pixels,
+            self.changed_pixels as f64 * 100.0 / self.pixels as f64
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+
+    #[test]
+    fn test_identical_images() {
+        let planes = Planes::new(&testing_image(20, 20, 2, 8), None).unwrap();
+        let comparison = Comparison::new(&planes, &planes).unwrap();
+        assert_eq!(comparison.psnr(), f64::INFINITY);
+        assert!((comparison.ssim() - 1.0).abs() < 1e-9);
+        assert_eq!(comparison.max_diff(), 0);
+        assert_eq!(comparison.changed_pixels, 0);
+    }
+
+    #[test]
+    fn test_lsb_changes() {
+        let image = testing_image(20, 20, 6, 8);
+        let mut changed = image.clone();
+        // 修改 10 个像素的 G 通道最低位和 1 个像素的 alpha
+        for pixel in 0..10 {
+            changed.data[pixel * 4 + 1] ^= 1;
+        }
+        changed.data[50 * 4 + 3] ^= 0x10;
+        let comparison = Comparison::new(
+            &Planes::new(&image, None).unwrap(),
+            &Planes::new(&changed, None).unwrap(),
+        )
+        .unwrap();
+        let counts: Vec<usize> = comparison.channels.iter().map(|x| x.changed).collect();
+        assert_eq!(counts, [0, 10, 0, 1]);
+        assert_eq!(comparison.changed_pixels, 11);
+        assert_eq!(comparison.max_diff(), 16);
+        // MSE = 10 / 400
+        let expected = 10.0 * (255.0f64.powi(2) * 40.0).log10();
+        assert!((comparison.channels[1].psnr - expected).abs() < 1e-9);
+        assert!(comparison.ssim() > 0.99);
+
+        let heatmap = comparison.heatmap();
+        assert_eq!(heatmap.data[50 * 3..50 * 3 + 3], [255, 255, 255]);
+        assert_eq!(heatmap.data[99 * 3..99 * 3 + 3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let a = Planes::new(&testing_image(20, 20, 2, 8), None).unwrap();
+        let b = Planes::new(&testing_image(20, 21, 2, 8), None).unwrap();
+        assert!(Comparison::new(&a, &b).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::png::Png;
+use crate::stego::{bytes_to_bits, payload_from_bits, payload_len, KeyRng, PAYLOAD_OVERHEAD};
+use anyhow::{bail, Context, Error, Result};
+
+// PLTE：1 到 256 个调色板项，每项为 3 个字节的 RGB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Palette(Vec<[u8; 3]>);
+
+impl TryFrom<&Chunk> for Palette {
+    type Error = Error;
+
+    fn try_from(value: &Chunk) -> Result<Self, Self::Error> {
+        if value.chunk_type().bytes() != Png::PLTE {
+            bail!("Not a PLTE chunk");
+        }
+        let data = value.data();
+        if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+            bail!("PLTE data length must be a multiple of 3 and at most 768");
+        }
+        Ok(Self(data.chunks(3).map(|x| [x[0], x[1], x[2]]).collect()))
+    }
+}
+
+impl TryFrom<&Png> for Palette {
+    type Error = Error;
+
+    fn try_from(value: &Png) -> Result<Self, Self::Error> {
+        let chunk = value
+            .chunks()
+            .iter()
+            .find(|x| x.chunk_type().bytes() == Png::PLTE)
+            .with_context(|| "No PLTE chunk")?;
+        Palette::try_from(chunk)
+    }
+}
+
+impl Palette {
+    // 调色板项的颜色，超出范围的索引按黑色处理
+    pub(crate) fn rgb(&self, index: u8) -> [u8; 3] {
+        self.0.get(index as usize).copied().unwrap_or_default()
+    }
+
+    /*
+    EzStego：按亮度对调色板排序，相邻的两项（排名 2i 和 2i+1）颜色最接近，
+    排名的奇偶性即为嵌入的比特，修改时只在这两项之间切换，调色板本身不变。
+    返回每个调色板索引对应的排名。
+     */
+    fn luminance_ranks(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.0.len()).collect();
+        order.sort_by_key(|&i| {
+            let [r, g, b] = self.0[i];
+            299 * r as u32 + 587 * g as u32 + 114 * b as u32
+        });
+        let mut ranks = vec![0; self.0.len()];
+        for (rank, &index) in order.iter().enumerate() {
+            ranks[index] = rank;
+        }
+        ranks
+    }
+}
+
+struct Carrier {
+    ranks: Vec<usize>,
+    // 排名到索引的反查表
+    indices: Vec<u8>,
+    // 按密钥打乱后可用于嵌入的像素
+    pixels: Vec<usize>,
+}
+
+impl Carrier {
+    fn new(image: &Image, palette: &Palette, key: &str) -> Result<Carrier> {
+        if image.ihdr.color_type != 3 {
+            bail!("Palette embedding requires an indexed-color image");
+        }
+        let ranks = palette.luminance_ranks();
+        let mut indices = vec![0; ranks.len()];
+        for (index, &rank) in ranks.iter().enumerate() {
+            indices[rank] = index as u8;
+        }
+        // 调色板项数为奇数时，排名最后的一项没有配对，不能用于嵌入
+        let paired = ranks.len() - ranks.len() % 2;
+        let mut pixels: Vec<usize> = (0..image.ihdr.width as usize * image.ihdr.height as usize)
+            .filter(|&pixel| {
+                ranks
+                    .get(image.index(pixel) as usize)
+                    .is_some_and(|&rank| rank < paired)
+            })
+            .collect();
+        KeyRng::new(key).shuffle(&mut pixels);
+        Ok(Self {
+            ranks,
+            indices,
+            pixels,
+        })
+    }
+
+    fn bit(&self, image: &Image, pixel: usize) -> u8 {
+        (self.ranks[image.index(pixel) as usize] & 1) as u8
+    }
+}
+
+// 可嵌入的消息字节数（已扣除长度、块类型和 CRC）
+pub(crate) fn capacity(image: &Image, palette: &Palette) -> Result<usize> {
+    let carrier = Carrier::new(image, palette, "")?;
+    Ok((carrier.pixels.len() / 8).saturating_sub(PAYLOAD_OVERHEAD))
+}
+
+// 嵌入消息，返回被修改的像素数
+pub(crate) fn embed(
+    image: &mut Image,
+    palette: &Palette,
+    key: &str,
+    chunk: &Chunk,
+) -> Result<usize> {
+    let carrier = Carrier::new(image, palette, key)?;
+    let bits = bytes_to_bits(&chunk.as_bytes());
+    if bits.len() > carrier.pixels.len() {
+        bail!(
+            "Message too large: needs {} pixels, only {} available",
+            bits.len(),
+            carrier.pixels.len()
+        );
+    }
+
+    let mut changed = 0;
+    for (&pixel, &bit) in carrier.pixels.iter().zip(bits.iter()) {
+        if carrier.bit(image, pixel) != bit {
+            let rank = carrier.ranks[image.index(pixel) as usize];
+            image.set_index(pixel, carrier.indices[rank ^ 1]);
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+pub(crate) fn extract(image: &Image, palette: &Palette, key: &str) -> Result<Chunk> {
+    let carrier = Carrier::new(image, palette, key)?;
+    let bits: Vec<u8> = carrier
+        .pixels
+        .iter()
+        .map(|&pixel| carrier.bit(image, pixel))
+        .collect();
+    if bits.len() < 32 {
+        bail!("No embedded message found (wrong key?)");
+    }
+    let total_bits = payload_len(&bits)? * 8;
+    if total_bits > bits.len() {
+        bail!("No embedded message found (wrong key?)");
+    }
+    payload_from_bits(&bits[..total_bits])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    fn testing_palette(len: usize) -> Palette {
+        let data = (0..len)
+            .flat_map(|i| {
+                [
+                    (i * 37 % 256) as u8,
+                    (i * 91 % 256) as u8,
+                    (i * 13 % 256) as u8,
+                ]
+            })
+            .collect();
+        Palette::try_from(&Chunk::new(ChunkType::from_str("PLTE").unwrap(), data)).unwrap()
+    }
+
+    #[test]
+    fn test_palette_from_chunk() {
+        let palette = testing_palette(5);
+        assert_eq!(palette.0.len(), 5);
+        assert_eq!(palette.0[1], [37, 91, 13]);
+
+        let chunk = Chunk::new(ChunkType::from_str("PLTE").unwrap(), vec![1, 2]);
+        assert!(Palette::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_palette_round_trip() {
+        let palette = testing_palette(15);
+        let mut image = testing_image(64, 64, 3, 4);
+        let chunk = Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"palette secret".to_vec(),
+        );
+        embed(&mut image, &palette, "key", &chunk).unwrap();
+
+        let actual = extract(&image, &palette, "key").unwrap();
+        assert_eq!(actual.data_as_string().unwrap(), "palette secret");
+        assert!(extract(&image, &palette, "other key").is_err());
+    }
+
+    #[test]
+    fn test_palette_changes_stay_in_pairs() {
+        let palette = testing_palette(16);
+        let ranks = palette.luminance_ranks();
+        let original = testing_image(32, 32, 3, 4);
+        let mut image = original.clone();
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"pairs".to_vec());
+        embed(&mut image, &palette, "key", &chunk).unwrap();
+
+        for pixel in 0..32 * 32 {
+            let before = original.index(pixel) as usize;
+            let after = image.index(pixel) as usize;
+            assert_eq!(ranks[before] / 2, ranks[after] / 2);
+        }
+    }
+
+    #[test]
+    fn test_palette_requires_indexed_image() {
+        let palette = testing_palette(4);
+        let mut image = testing_image(8, 8, 2, 8);
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new());
+        assert!(embed(&mut image, &palette, "key", &chunk).is_err());
+    }
+}
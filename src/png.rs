@@ -1,4 +1,5 @@
 use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
 use anyhow::{bail, Context, Error, Result};
 use std::fmt::{Display, Formatter};
 use std::io::{BufRead, BufReader, Read};
@@ -7,6 +8,8 @@ use std::io::{BufRead, BufReader, Read};
 pub(crate) struct Png {
     signature: [u8; 8],
     chunks: Vec<Chunk>,
+    // IEND 之后的数据（例如 PNG/ZIP 多格式文件中的 ZIP 部分），原样保留
+    trailer: Vec<u8>,
 }
 
 impl TryFrom<&[u8]> for Png {
@@ -24,13 +27,20 @@ impl TryFrom<&[u8]> for Png {
 
         let mut all_chunks_bytes: Vec<Vec<u8>> = Vec::new();
         let mut chunk_data_len_bytes = [0; 4];
-        while !reader.buffer().is_empty() {
+        while !reader.fill_buf()?.is_empty() {
             reader.read_exact(&mut chunk_data_len_bytes)?;
             let chunk_data_length = u32::from_be_bytes(chunk_data_len_bytes);
             let mut remain_chunk_bytes = vec![0; 4 + chunk_data_length as usize + 4];
             reader.read_exact(&mut remain_chunk_bytes)?;
+            let is_iend = remain_chunk_bytes[..4] == Self::IEND;
             all_chunks_bytes.push([chunk_data_len_bytes.to_vec(), remain_chunk_bytes].concat());
+            // IEND 之后的内容不再按块解析
+            if is_iend {
+                break;
+            }
         }
+        let mut trailer = Vec::new();
+        reader.read_to_end(&mut trailer)?;
 
         let mut chunks = Vec::new();
         for chunk_bytes in all_chunks_bytes {
@@ -40,6 +50,7 @@ impl TryFrom<&[u8]> for Png {
         Ok(Self {
             signature: Self::STANDARD_HEADER,
             chunks,
+            trailer,
         })
     }
 }
@@ -55,7 +66,11 @@ impl Display for Png {
                 msg_list.push(format!("\"{}\"", s));
             }
         }
-        f.write_str(&format!("Embedded message: [{}]", msg_list.join(", ")))
+        f.write_str(&format!("Embedded message: [{}]", msg_list.join(", ")))?;
+        if !self.trailer.is_empty() {
+            write!(f, "\nTrailer: {} bytes after IEND", self.trailer.len())?;
+        }
+        Ok(())
     }
 }
 
@@ -69,11 +84,16 @@ impl Png {
         [103, 65, 77, 65],  // gAMA
         [112, 72, 89, 115], // pHYs
     ];
+    pub(crate) const IHDR: [u8; 4] = [73, 72, 68, 82];
+    pub(crate) const PLTE: [u8; 4] = [80, 76, 84, 69];
+    pub(crate) const IDAT: [u8; 4] = [73, 68, 65, 84];
+    pub(crate) const IEND: [u8; 4] = [73, 69, 78, 68];
 
-    fn from_chunks(chunks: Vec<Chunk>) -> Png {
+    pub(crate) fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Self {
             signature: Self::STANDARD_HEADER,
             chunks,
+            trailer: Vec::new(),
         }
     }
 
@@ -91,24 +111,106 @@ impl Png {
         Ok(self.chunks.remove(first_index))
     }
 
+    // 最后一个块不是 IEND 时在末尾补上，返回是否补上了
+    pub(crate) fn ensure_iend(&mut self) -> Result<bool> {
+        if self
+            .chunks
+            .last()
+            .is_some_and(|x| x.chunk_type().bytes() == Self::IEND)
+        {
+            return Ok(false);
+        }
+        self.chunks
+            .push(Chunk::new(ChunkType::try_from(Self::IEND)?, Vec::new()));
+        Ok(true)
+    }
+
+    // 删除所有满足条件的块，按原来的顺序返回被删除的块
+    pub(crate) fn remove_chunks<F: FnMut(&Chunk) -> bool>(
+        &mut self,
+        mut predicate: F,
+    ) -> Vec<Chunk> {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|x| predicate(x));
+        self.chunks = kept;
+        removed
+    }
+
+    /*
+    安全复制位为 0 的未知辅助块依赖于关键块的内容，PNG 规范要求修改了 IDAT 或 PLTE 之后不能原样保留。
+    已注册的块由本工具理解，像素的修改不影响它们的含义。返回被删除的块。
+     */
+    pub(crate) fn remove_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        self.remove_chunks(|x| {
+            let chunk_type = x.chunk_type();
+            !chunk_type.is_critical()
+                && !chunk_type.is_safe_to_copy()
+                && !chunk_type.is_registered()
+        })
+    }
+
+    #[allow(dead_code)]
     fn header(&self) -> &[u8; 8] {
         &Self::STANDARD_HEADER
     }
 
-    fn chunks(&self) -> &[Chunk] {
+    pub(crate) fn chunks(&self) -> &[Chunk] {
         self.chunks.as_slice()
     }
 
+    pub(crate) fn chunks_mut(&mut self) -> &mut [Chunk] {
+        self.chunks.as_mut_slice()
+    }
+
     pub(crate) fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         self.chunks
             .iter()
             .find(|&x| x.chunk_type().to_string() == chunk_type)
     }
 
+    // 所有 IDAT 块的数据拼接起来才是完整的 zlib 数据流
+    pub(crate) fn image_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|x| x.chunk_type().bytes() == Self::IDAT)
+            .flat_map(|x| x.data().iter().copied())
+            .collect()
+    }
+
+    // 用单个 IDAT 块替换原有的全部 IDAT 块，位置与第一个 IDAT 块相同
+    pub(crate) fn set_image_data(&mut self, data: Vec<u8>) -> Result<()> {
+        self.set_image_chunks(vec![data])
+    }
+
+    // 将数据流按给定的分段放入连续的多个 IDAT 块
+    pub(crate) fn set_image_chunks(&mut self, parts: Vec<Vec<u8>>) -> Result<()> {
+        let first_index = self
+            .chunks
+            .iter()
+            .position(|x| x.chunk_type().bytes() == Self::IDAT)
+            .with_context(|| "No IDAT chunk")?;
+        self.chunks.retain(|x| x.chunk_type().bytes() != Self::IDAT);
+        for (i, data) in parts.into_iter().enumerate() {
+            let chunk = Chunk::new(ChunkType::try_from(Self::IDAT)?, data);
+            self.chunks.insert(first_index + i, chunk);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    pub(crate) fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = trailer;
+    }
+
     pub(crate) fn as_bytes(&self) -> Vec<u8> {
         [
             self.signature.to_vec(),
             self.chunks.iter().flat_map(|x| x.as_bytes()).collect(),
+            self.trailer.clone(),
         ]
         .concat()
     }
@@ -117,7 +219,6 @@ impl Png {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::chunk_type::ChunkType;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
@@ -241,6 +342,39 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunks() {
+        let mut png = testing_png();
+        let removed = png.remove_chunks(|x| !x.chunk_type().is_critical());
+        let removed: Vec<String> = removed.iter().map(|x| x.chunk_type().to_string()).collect();
+        assert_eq!(removed, ["miDl"]);
+        assert_eq!(png.chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_remove_unsafe_to_copy() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("ruST", "unsafe").unwrap());
+        png.append_chunk(chunk_from_strings("ruSt", "safe").unwrap());
+        png.append_chunk(chunk_from_strings("gAMA", "gama").unwrap());
+        let removed = png.remove_unsafe_to_copy();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].chunk_type().to_string(), "ruST");
+        assert_eq!(png.chunks().len(), 5);
+    }
+
+    #[test]
+    fn test_ensure_iend() {
+        let mut png = testing_png();
+        assert!(png.ensure_iend().unwrap());
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+        assert!(!png.ensure_iend().unwrap());
+        assert_eq!(png.chunks().len(), 4);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -255,6 +389,20 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_trailer_after_iend() {
+        let trailer = b"PK\x03\x04 not a chunk".to_vec();
+        let bytes = [PNG_FILE.to_vec(), trailer.clone()].concat();
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.trailer(), trailer.as_slice());
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+        assert_eq!(png.as_bytes(), bytes);
+        assert!(png.to_string().contains("Trailer: 16 bytes after IEND"));
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
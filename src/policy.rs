@@ -0,0 +1,281 @@
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use anyhow::{Error, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/*
+上传服务使用的块规则，TOML 格式：
+
+allow = ["IHDR", "PLTE", "IDAT", "IEND", "tRNS", "gAMA", "sRGB"]  # 省略时允许所有类型
+deny = ["tEXt", "zTXt", "iTXt"]
+require = ["IHDR", "IDAT", "IEND"]
+max_ancillary_bytes = 65536
+trailer = "strip"  # allow、strip 或 reject
+
+[max_size]
+iCCP = 100000
+
+不允许或过大的辅助块直接删除；关键块不能删除，同缺少必需的块、辅助块总量超限一样算作违规。
+有违规时文件应当被拒绝。
+ */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Policy {
+    allow: Option<Vec<String>>,
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    require: Vec<String>,
+    #[serde(default)]
+    max_size: BTreeMap<String, usize>,
+    max_ancillary_bytes: Option<usize>,
+    #[serde(default)]
+    trailer: Trailer,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Trailer {
+    #[default]
+    Allow,
+    Strip,
+    Reject,
+}
+
+pub(crate) struct Report {
+    // 被删除的块：类型、数据长度和原因
+    pub(crate) removed: Vec<(String, usize, String)>,
+    pub(crate) trailer_removed: usize,
+    pub(crate) violations: Vec<String>,
+}
+
+impl Report {
+    pub(crate) fn is_accepted(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            if self.is_accepted() {
+                "Accepted"
+            } else {
+                "Rejected"
+            }
+        )?;
+        for (chunk_type, len, reason) in &self.removed {
+            write!(f, "\n  removed {} ({} bytes): {}", chunk_type, len, reason)?;
+        }
+        if self.trailer_removed > 0 {
+            write!(f, "\n  removed {} bytes after IEND", self.trailer_removed)?;
+        }
+        for violation in &self.violations {
+            write!(f, "\n  violation: {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy: Policy = toml::from_str(s)?;
+        let types = policy
+            .allow
+            .iter()
+            .flatten()
+            .chain(&policy.deny)
+            .chain(&policy.require)
+            .chain(policy.max_size.keys());
+        for chunk_type in types {
+            ChunkType::from_str(chunk_type)?;
+        }
+        Ok(policy)
+    }
+}
+
+impl Policy {
+    // 不允许存在的原因，允许时返回 None
+    fn rejection(&self, chunk_type: &str, len: usize) -> Option<String> {
+        if self.deny.iter().any(|x| x == chunk_type) {
+            return Some("denied".to_string());
+        }
+        if self
+            .allow
+            .as_ref()
+            .is_some_and(|allow| !allow.iter().any(|x| x == chunk_type))
+        {
+            return Some("not in the allow list".to_string());
+        }
+        match self.max_size.get(chunk_type) {
+            Some(&max) if len > max => Some(format!("larger than {} bytes", max)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn apply(&self, png: &mut Png) -> Report {
+        let mut violations = Vec::new();
+        for chunk in png.chunks() {
+            let chunk_type = chunk.chunk_type();
+            if !chunk_type.is_critical() {
+                continue;
+            }
+            let name = chunk_type.to_string();
+            if let Some(reason) = self.rejection(&name, chunk.data().len()) {
+                violations.push(format!("critical chunk {} is {}", name, reason));
+            }
+        }
+
+        let mut removed = Vec::new();
+        png.remove_chunks(|chunk| {
+            let chunk_type = chunk.chunk_type();
+            let name = chunk_type.to_string();
+            let len = chunk.data().len();
+            match self.rejection(&name, len) {
+                Some(reason) if !chunk_type.is_critical() => {
+                    removed.push((name, len, reason));
+                    true
+                }
+                _ => false,
+            }
+        });
+
+        for chunk_type in &self.require {
+            if png.chunk_by_type(chunk_type).is_none() {
+                violations.push(format!("required chunk {} is missing", chunk_type));
+            }
+        }
+        let ancillary: usize = png
+            .chunks()
+            .iter()
+            .filter(|x| !x.chunk_type().is_critical())
+            .map(|x| x.data().len())
+            .sum();
+        if let Some(max) = self.max_ancillary_bytes.filter(|&max| ancillary > max) {
+            violations.push(format!(
+                "{} bytes of ancillary chunks, at most {} allowed",
+                ancillary, max
+            ));
+        }
+
+        let mut trailer_removed = 0;
+        if !png.trailer().is_empty() {
+            match self.trailer {
+                Trailer::Allow => {}
+                Trailer::Strip => {
+                    trailer_removed = png.trailer().len();
+                    png.set_trailer(Vec::new());
+                }
+                Trailer::Reject => {
+                    violations.push(format!("{} bytes after IEND", png.trailer().len()))
+                }
+            }
+        }
+        Report {
+            removed,
+            trailer_removed,
+            violations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::image::tests::testing_image;
+
+    const POLICY: &str = r#"
+        allow = ["IHDR", "IDAT", "IEND", "gAMA", "iCCP", "pHYs"]
+        deny = ["pHYs"]
+        require = ["IHDR", "IDAT", "IEND", "gAMA"]
+        max_ancillary_bytes = 100
+        trailer = "strip"
+
+        [max_size]
+        iCCP = 50
+    "#;
+
+    fn testing_png(chunks: &[(&str, usize)]) -> Png {
+        let mut png = testing_image(8, 8, 2, 8).to_png().unwrap();
+        for &(chunk_type, len) in chunks {
+            png.append_chunk(Chunk::new(
+                ChunkType::from_str(chunk_type).unwrap(),
+                vec![0; len],
+            ));
+        }
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let policy = Policy::from_str(POLICY).unwrap();
+        assert_eq!(policy.deny, ["pHYs"]);
+        assert_eq!(policy.max_size["iCCP"], 50);
+        assert!(policy.trailer == Trailer::Strip);
+
+        assert!(Policy::from_str("deny = [\"toolong\"]").is_err());
+        assert!(Policy::from_str("trailer = \"keep\"").is_err());
+        assert!(Policy::from_str("unknown = 1").is_err());
+        assert!(Policy::from_str("").unwrap().allow.is_none());
+    }
+
+    #[test]
+    fn test_remove_ancillary_chunks() {
+        let policy = Policy::from_str(POLICY).unwrap();
+        let mut png = testing_png(&[("gAMA", 4), ("tEXt", 10), ("pHYs", 9), ("iCCP", 60)]);
+        png.set_trailer(b"zip".to_vec());
+        let report = policy.apply(&mut png);
+        assert!(report.is_accepted());
+        assert_eq!(types(&png), ["IHDR", "IDAT", "gAMA", "IEND"]);
+        let reasons: Vec<&str> = report.removed.iter().map(|x| x.2.as_str()).collect();
+        assert_eq!(
+            reasons,
+            ["not in the allow list", "denied", "larger than 50 bytes"]
+        );
+        assert_eq!(report.trailer_removed, 3);
+        assert!(png.trailer().is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let policy = Policy::from_str(POLICY).unwrap();
+        let mut png = testing_png(&[("RuSt", 2), ("iCCP", 50), ("iCCP", 50)]);
+        let report = policy.apply(&mut png);
+        assert!(!report.is_accepted());
+        assert_eq!(
+            report.violations,
+            [
+                "critical chunk RuSt is not in the allow list",
+                "required chunk gAMA is missing"
+            ]
+        );
+
+        let policy = Policy::from_str("trailer = \"reject\"\nmax_ancillary_bytes = 10").unwrap();
+        let mut png = testing_png(&[("tEXt", 11)]);
+        png.set_trailer(vec![0]);
+        let report = policy.apply(&mut png);
+        assert_eq!(
+            report.violations,
+            [
+                "11 bytes of ancillary chunks, at most 10 allowed",
+                "1 bytes after IEND"
+            ]
+        );
+        assert_eq!(png.trailer().len(), 1);
+    }
+}
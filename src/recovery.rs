@@ -0,0 +1,392 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use std::fmt::{Display, Formatter};
+
+/*
+宽松的解析方式，用于损坏或下载不完整的文件：
+- 签名不正确时从文件开头查找第一个合理的块
+- CRC 不匹配的块仍然保留（重新计算 CRC），并记录下来
+- 遇到无法解析的数据时跳过，直到下一个合理的块（已注册的类型且长度不超出文件，或者 CRC 正确且长度不超过
+  MAX_UNREGISTERED_LEN，避免在大量垃圾数据中每个位置都计算很长的 CRC）
+- CRC 不匹配、后面又不是合理的块时，长度字段可能超出了真正的数据：块中间有合理的块时从那里截断，
+  否则记录为 Overrun
+- 文件在块的中间结束时保留已有的部分数据
+返回尽可能完整的 Png 和损坏记录，完好的块中的消息仍然可以读取。
+ */
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Damage {
+    Signature,
+    Crc {
+        offset: usize,
+        chunk_type: String,
+    },
+    Garbage {
+        offset: usize,
+        len: usize,
+    },
+    // present 和 expected 为整个块（含长度、类型和 CRC）的字节数，块头不完整时没有类型
+    Truncated {
+        offset: usize,
+        chunk_type: Option<String>,
+        present: usize,
+        expected: usize,
+    },
+    // CRC 不匹配且后面没有合理的块，块的数据中可能混入了无关的数据
+    Overrun {
+        offset: usize,
+        chunk_type: String,
+    },
+    MissingIend,
+}
+
+const MAX_UNREGISTERED_LEN: usize = 1 << 20;
+
+impl Display for Damage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Damage::Signature => write!(f, "offset 0: invalid png signature"),
+            Damage::Crc { offset, chunk_type } => {
+                write!(f, "offset {}: CRC mismatch in {} chunk", offset, chunk_type)
+            }
+            Damage::Garbage { offset, len } => {
+                write!(f, "offset {}: skipped {} unparsable bytes", offset, len)
+            }
+            Damage::Truncated {
+                offset,
+                chunk_type,
+                present,
+                expected,
+            } => write!(
+                f,
+                "offset {}: {} chunk truncated, {} of {} bytes present",
+                offset,
+                chunk_type.as_deref().unwrap_or("unknown"),
+                present,
+                expected
+            ),
+            Damage::Overrun { offset, chunk_type } => write!(
+                f,
+                "offset {}: CRC mismatch in {} chunk and no chunk follows it, its length may run into unrelated data",
+                offset, chunk_type
+            ),
+            Damage::MissingIend => write!(f, "missing IEND chunk"),
+        }
+    }
+}
+
+pub(crate) struct Recovered {
+    pub(crate) png: Png,
+    pub(crate) damage: Vec<Damage>,
+}
+
+// offset 处的块头：数据长度和块类型
+fn header(bytes: &[u8], offset: usize) -> Option<(usize, ChunkType)> {
+    let header = bytes.get(offset..offset.checked_add(8)?)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    // 先检查再构造，避免在垃圾数据的每个位置都构造错误值
+    if !header[4..].iter().all(u8::is_ascii_alphabetic) {
+        return None;
+    }
+    let chunk_type = ChunkType::try_from([header[4], header[5], header[6], header[7]]).ok()?;
+    Some((length, chunk_type))
+}
+
+// offset 处是否像是一个块的开始
+fn plausible(bytes: &[u8], offset: usize) -> bool {
+    let Some((length, chunk_type)) = header(bytes, offset) else {
+        return false;
+    };
+    if !chunk_type.is_registered() && length > MAX_UNREGISTERED_LEN {
+        return false;
+    }
+    let Some(chunk_bytes) = offset
+        .checked_add(12 + length)
+        .and_then(|end| bytes.get(offset..end))
+    else {
+        return false;
+    };
+    chunk_type.is_registered() || Chunk::try_from(chunk_bytes).is_ok()
+}
+
+pub(crate) fn recover(bytes: &[u8]) -> Recovered {
+    let mut damage = Vec::new();
+    let mut offset = Png::STANDARD_HEADER.len();
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        damage.push(Damage::Signature);
+        offset = 0;
+        if !plausible(bytes, offset) {
+            offset = (0..bytes.len())
+                .find(|&x| plausible(bytes, x))
+                .unwrap_or(bytes.len());
+            if offset > 0 {
+                damage.push(Damage::Garbage {
+                    offset: 0,
+                    len: offset,
+                });
+            }
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut found_iend = false;
+    while offset < bytes.len() {
+        let remaining = bytes.len() - offset;
+        let Some((length, chunk_type)) = header(bytes, offset) else {
+            if remaining < 8 {
+                damage.push(Damage::Truncated {
+                    offset,
+                    chunk_type: None,
+                    present: remaining,
+                    expected: 12,
+                });
+                offset = bytes.len();
+                break;
+            }
+            // 块类型无效，跳到下一个合理的块
+            let next = (offset + 1..bytes.len())
+                .find(|&x| plausible(bytes, x))
+                .unwrap_or(bytes.len());
+            damage.push(Damage::Garbage {
+                offset,
+                len: next - offset,
+            });
+            offset = next;
+            continue;
+        };
+
+        let expected = 12usize.saturating_add(length);
+        if expected > remaining {
+            // 长度字段损坏时后面可能还有完好的块，否则是文件被截断
+            if let Some(next) = (offset + 1..bytes.len()).find(|&x| plausible(bytes, x)) {
+                damage.push(Damage::Garbage {
+                    offset,
+                    len: next - offset,
+                });
+                offset = next;
+                continue;
+            }
+            damage.push(Damage::Truncated {
+                offset,
+                chunk_type: Some(chunk_type.to_string()),
+                present: remaining,
+                expected,
+            });
+            let data = bytes[offset + 8..].to_vec();
+            chunks.push(Chunk::new(chunk_type, data));
+            offset = bytes.len();
+            break;
+        }
+
+        let end = offset + expected;
+        let chunk = match Chunk::try_from(&bytes[offset..end]) {
+            Ok(chunk) => chunk,
+            // 后面是合理的块或者数据结束，只是数据损坏
+            Err(_) if end == bytes.len() || plausible(bytes, end) => {
+                damage.push(Damage::Crc {
+                    offset,
+                    chunk_type: chunk_type.to_string(),
+                });
+                Chunk::new(chunk_type, bytes[offset + 8..end - 4].to_vec())
+            }
+            Err(_) => {
+                if let Some(next) = (offset + 8..end).find(|&x| plausible(bytes, x)) {
+                    damage.push(Damage::Truncated {
+                        offset,
+                        chunk_type: Some(chunk_type.to_string()),
+                        present: next - offset,
+                        expected,
+                    });
+                    chunks.push(Chunk::new(chunk_type, bytes[offset + 8..next].to_vec()));
+                    offset = next;
+                    continue;
+                }
+                damage.push(Damage::Overrun {
+                    offset,
+                    chunk_type: chunk_type.to_string(),
+                });
+                Chunk::new(chunk_type, bytes[offset + 8..end - 4].to_vec())
+            }
+        };
+        offset = end;
+        found_iend = chunk.chunk_type().bytes() == Png::IEND;
+        chunks.push(chunk);
+        if found_iend {
+            break;
+        }
+    }
+
+    if !found_iend {
+        damage.push(Damage::MissingIend);
+    }
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailer(bytes.get(offset..).unwrap_or_default().to_vec());
+    Recovered { png, damage }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"still readable".to_vec(),
+        ));
+        png.as_bytes()
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_intact_file() {
+        let bytes = testing_bytes();
+        let recovered = recover(&bytes);
+        assert!(recovered.damage.is_empty());
+        assert_eq!(recovered.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = testing_bytes();
+        let len = bytes.len();
+        // ruSt 块的 CRC 在 IEND（12 字节）之前
+        bytes[len - 13] ^= 0xff;
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+        let recovered = recover(&bytes);
+        let offset = len - 12 - 26;
+        assert_eq!(
+            recovered.damage,
+            [Damage::Crc {
+                offset,
+                chunk_type: "ruSt".to_string()
+            }]
+        );
+        let chunk = recovered.png.chunk_by_type("ruSt").unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "still readable");
+    }
+
+    #[test]
+    fn test_garbage_between_chunks() {
+        let bytes = testing_bytes();
+        let len = bytes.len();
+        // 在 ruSt 块之前插入无法解析的数据
+        let at = len - 12 - 26;
+        let damaged = [
+            &bytes[..at],
+            &[0xde, 0xad, 0xbe, 0xef, 1, 2, 3][..],
+            &bytes[at..],
+        ]
+        .concat();
+        let recovered = recover(&damaged);
+        assert_eq!(recovered.damage, [Damage::Garbage { offset: at, len: 7 }]);
+        assert_eq!(types(&recovered.png), ["IHDR", "IDAT", "ruSt", "IEND"]);
+        assert!(Image::try_from(&recovered.png).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = testing_bytes();
+        let len = bytes.len();
+        let recovered = recover(&bytes[..len - 12 - 10]);
+        assert_eq!(
+            recovered.damage,
+            [
+                Damage::Truncated {
+                    offset: len - 12 - 26,
+                    chunk_type: Some("ruSt".to_string()),
+                    present: 16,
+                    expected: 26
+                },
+                Damage::MissingIend
+            ]
+        );
+        assert_eq!(types(&recovered.png), ["IHDR", "IDAT", "ruSt"]);
+        assert_eq!(
+            recovered.png.chunks()[2].data_as_string().unwrap(),
+            "still re"
+        );
+
+        assert!(recovered.png.trailer().is_empty());
+
+        let recovered = recover(&bytes[..len - 7]);
+        assert_eq!(
+            recovered.damage[0].to_string(),
+            format!(
+                "offset {}: unknown chunk truncated, 5 of 12 bytes present",
+                len - 12
+            )
+        );
+    }
+
+    // 确定性的伪随机数据
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_length_runs_into_next_chunk() {
+        // IDAT 的后半部分丢失，后面紧接着完好的块
+        let bytes = testing_bytes();
+        let len = bytes.len();
+        let idat_end = len - 12 - 26;
+        let damaged = [&bytes[..idat_end - 30], &bytes[idat_end..]].concat();
+        let recovered = recover(&damaged);
+        let idat = 8 + 25;
+        assert_eq!(
+            recovered.damage,
+            [Damage::Truncated {
+                offset: idat,
+                chunk_type: Some("IDAT".to_string()),
+                present: idat_end - 30 - idat,
+                expected: idat_end - idat
+            }]
+        );
+        assert_eq!(types(&recovered.png), ["IHDR", "IDAT", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_length_runs_into_garbage() {
+        let bytes = testing_bytes();
+        let idat_end = bytes.len() - 12 - 26;
+        let damaged = [&bytes[..idat_end - 30], &noise(1000)].concat();
+        let recovered = recover(&damaged);
+        assert_eq!(
+            recovered.damage[0],
+            Damage::Overrun {
+                offset: 8 + 25,
+                chunk_type: "IDAT".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let bytes = testing_bytes();
+        // 文本方式传输把 \r\n 换成了 \n
+        let damaged = [&bytes[..4], &bytes[5..]].concat();
+        let recovered = recover(&damaged);
+        assert_eq!(
+            recovered.damage,
+            [Damage::Signature, Damage::Garbage { offset: 0, len: 7 }]
+        );
+        assert_eq!(types(&recovered.png), ["IHDR", "IDAT", "ruSt", "IEND"]);
+    }
+}
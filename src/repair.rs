@@ -0,0 +1,407 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::recovery::{self, Damage};
+use anyhow::Result;
+use std::collections::HashMap;
+
+/*
+修复常见的损坏：
+- 文本方式传输造成的换行转换。签名中的 \r\n 和单独的 \n 正是为了发现这种问题：
+  LF -> CRLF 后签名变为 \r\r\n...\r\n，把全文件的 \r\n 换回 \n 即可完全还原；
+  CRLF -> LF 后签名变为 \n...\n，这种转换会丢失信息：逐个块按长度字段得知丢失了几个 \r，
+  再按 CRC 找出它们在哪些 \n 之前（见 locate_lost_cr），找不到或不唯一时放弃修复
+- CRC 错误的块重新计算 CRC，无法解析的数据跳过（见 recovery）。还原过换行的数据中 CRC 仍然错误时
+  说明还有别的损坏，不重新计算
+- 缺少 IEND 时在末尾补上
+ */
+const LF_TO_CRLF: [u8; 10] = [
+    0x89, b'P', b'N', b'G', b'\r', b'\r', b'\n', 0x1a, b'\r', b'\n',
+];
+const CRLF_TO_LF: [u8; 7] = [0x89, b'P', b'N', b'G', b'\n', 0x1a, b'\n'];
+// 每个块最多找回的 \r 个数，以及最多尝试的组合数（组合越多，CRC 偶然相同的可能越大）
+const MAX_LOST_CR: usize = 2;
+const MAX_CANDIDATES: usize = 1 << 24;
+
+pub(crate) struct Repaired {
+    pub(crate) png: Png,
+    // 做过的修复，按顺序
+    pub(crate) fixes: Vec<String>,
+    // 无法修复的损坏，不为空时不应当使用修复的结果
+    pub(crate) unrepaired: Vec<String>,
+}
+
+fn replace(bytes: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(from) {
+            result.extend_from_slice(to);
+            i += from.len();
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+/*
+CRC-32 在 GF(2) 上是仿射的：同样长度的数据，CRC(m) = CRC(0...0) ^ L(m)，L 是初值为 0、不取反的 CRC，
+对数据是线性的。在数据后面补 n 个 0 字节相当于把 L 乘以 x^(8n) mod P。
+下面使用反射表示（与 zlib 的 crc32_combine 相同）。
+ */
+const POLY: u32 = 0xedb8_8320;
+
+fn linear_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+fn linear_update(table: &[u32; 256], crc: u32, byte: u8) -> u32 {
+    table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+}
+
+// a * b mod P
+fn multiply(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut m = 1 << 31;
+    while m != 0 {
+        if a & m != 0 {
+            product ^= b;
+        }
+        b = if b & 1 != 0 { (b >> 1) ^ POLY } else { b >> 1 };
+        m >>= 1;
+    }
+    product
+}
+
+// x^(8n) mod P
+fn zero_bytes(n: usize) -> u32 {
+    let mut result = 1 << 31;
+    let mut power = 1 << 23; // x^8
+    let mut n = n;
+    while n != 0 {
+        if n & 1 != 0 {
+            result = multiply(power, result);
+        }
+        power = multiply(power, power);
+        n >>= 1;
+    }
+    result
+}
+
+/*
+被 CRC 覆盖的区域 covered（块类型和数据）丢失了 k 个 \r，每个 \r 原来在某个 \n 之前。
+对插入位置 r（r 之前的字节保持不动），记 Z(r) 为只保留 covered[..r]、其余置 0 的 L 值，
+W(r) 为只在 r 处放一个 \r、后面跟 len - r 个 0 的 L 值，T 为整个 covered 的 L 值。插入 \r 后：
+  k = 1：L = shift1(Z) ^ Z ^ W ^ T
+  k = 2（r1 < r2）：L = [shift2(Z1) ^ shift1(Z1) ^ shift1(W1)] ^ [shift1(Z2) ^ Z2 ^ W2] ^ T
+两部分互相独立，用哈希表就能在线性时间内找到所有符合 CRC 的组合。
+返回唯一解的插入位置，没有解或解不唯一时返回 None。
+ */
+fn locate_lost_cr(covered: &[u8], candidates: &[usize], k: usize, crc: u32) -> Option<Vec<usize>> {
+    let table = linear_table();
+    let len = covered.len();
+    let total_len = len + k;
+    // 目标 L 值：CRC ^ CRC(0...0)
+    let target = crc ^ !multiply(zero_bytes(total_len), !0);
+
+    let mut prefix = 0;
+    let mut done = 0;
+    let mut z = Vec::with_capacity(candidates.len());
+    let mut w = Vec::with_capacity(candidates.len());
+    let cr = linear_update(&table, 0, b'\r');
+    for &r in candidates {
+        for &byte in &covered[done..r] {
+            prefix = linear_update(&table, prefix, byte);
+        }
+        done = r;
+        let shift = zero_bytes(len - r);
+        z.push(multiply(shift, prefix));
+        w.push(multiply(shift, cr));
+    }
+    for &byte in &covered[done..] {
+        prefix = linear_update(&table, prefix, byte);
+    }
+    let total = prefix;
+
+    let shift1 = zero_bytes(1);
+    let shift2 = zero_bytes(2);
+    let mut found = Vec::new();
+    match k {
+        0 => {
+            if total == target {
+                found.push(Vec::new());
+            }
+        }
+        1 => {
+            for i in 0..candidates.len() {
+                if multiply(shift1, z[i]) ^ z[i] ^ w[i] ^ total == target {
+                    found.push(vec![candidates[i]]);
+                }
+            }
+        }
+        2 => {
+            let mut firsts: HashMap<u32, Vec<usize>> = HashMap::new();
+            for j in 0..candidates.len() {
+                let second = multiply(shift1, z[j]) ^ z[j] ^ w[j];
+                if let Some(firsts) = firsts.get(&(target ^ total ^ second)) {
+                    found.extend(firsts.iter().map(|&i| vec![candidates[i], candidates[j]]));
+                }
+                let first = multiply(shift2, z[j]) ^ multiply(shift1, z[j] ^ w[j]);
+                firsts.entry(first).or_default().push(j);
+            }
+        }
+        _ => unreachable!(),
+    }
+    match found.len() {
+        1 => found.pop(),
+        _ => None,
+    }
+}
+
+// 还原 offset 处被 CRLF -> LF 转换过的块，返回原来的块和转换后占用的字节数
+fn restore_chunk(bytes: &[u8], offset: usize) -> Option<(Vec<u8>, usize)> {
+    let header = bytes.get(offset..offset.checked_add(8)?)?;
+    // 长度字段本身被转换过时无法得知原来的长度
+    if header.windows(2).any(|x| x == b"\r\n") {
+        return None;
+    }
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let expected = length.checked_add(12)?;
+    for k in 0..=MAX_LOST_CR.min(length) {
+        let Some(window) = bytes.get(offset..offset + expected - k) else {
+            continue;
+        };
+        // 只考虑 CRC 本身完好的情况，\r 可以插入到数据中任意一个 \n 之前（包括 CRC 的第一个字节）
+        let crc_start = window.len() - 4;
+        let crc = u32::from_be_bytes(window[crc_start..].try_into().ok()?);
+        let candidates: Vec<usize> = (8..=crc_start)
+            .filter(|&x| window[x] == b'\n')
+            .map(|x| x - 4)
+            .collect();
+        let combinations = match k {
+            0 => 1,
+            1 => candidates.len(),
+            _ => candidates.len() * candidates.len().saturating_sub(1) / 2,
+        };
+        if combinations > MAX_CANDIDATES {
+            return None;
+        }
+        let covered = &window[4..crc_start];
+        let Some(positions) = locate_lost_cr(covered, &candidates, k, crc) else {
+            continue;
+        };
+        let mut chunk = window[..4].to_vec();
+        let mut start = 0;
+        for &x in &positions {
+            chunk.extend_from_slice(&covered[start..x]);
+            chunk.push(b'\r');
+            start = x;
+        }
+        chunk.extend_from_slice(&covered[start..]);
+        chunk.extend_from_slice(&window[crc_start..]);
+        // 用块本身的 CRC 校验再确认一次
+        Chunk::try_from(chunk.as_ref()).ok()?;
+        return Some((chunk, window.len()));
+    }
+    None
+}
+
+/*
+逐个块还原，返回还原后的数据和无法还原时的原因。
+某个块无法还原时，从它开始的数据保持原样，交给 recovery 标记为损坏
+ */
+fn undo_crlf_to_lf(bytes: &[u8]) -> (Vec<u8>, Option<String>) {
+    let mut result = Png::STANDARD_HEADER.to_vec();
+    let mut offset = CRLF_TO_LF.len();
+    let mut failure = None;
+    while offset < bytes.len() {
+        let Some((chunk, len)) = restore_chunk(bytes, offset) else {
+            let chunk_type = bytes
+                .get(offset + 4..offset + 8)
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+            failure = Some(format!(
+                "cannot undo CRLF to LF conversion: the line breaks lost in the {} chunk at input offset {} could not be located",
+                chunk_type, offset
+            ));
+            break;
+        };
+        result.extend_from_slice(&chunk);
+        offset += len;
+        if chunk[4..8] == Png::IEND {
+            break;
+        }
+    }
+    // IEND 之后的数据无法校验，保持原样
+    result.extend_from_slice(&bytes[offset..]);
+    (result, failure)
+}
+
+pub(crate) fn repair(bytes: &[u8]) -> Result<Repaired> {
+    let mut fixes = Vec::new();
+    let mut unrepaired = Vec::new();
+    // 根据签名判断是否发生过换行转换
+    let converted = if bytes.starts_with(&LF_TO_CRLF) {
+        fixes.push("undid LF to CRLF conversion".to_string());
+        Some(replace(bytes, b"\r\n", b"\n"))
+    } else if bytes.starts_with(&CRLF_TO_LF) {
+        let (restored, failure) = undo_crlf_to_lf(bytes);
+        match failure {
+            Some(failure) => unrepaired.push(failure),
+            None => fixes.push("undid CRLF to LF conversion".to_string()),
+        }
+        Some(restored)
+    } else {
+        None
+    };
+    let bytes = converted.as_deref().unwrap_or(bytes);
+
+    let mut recovered = recovery::recover(bytes);
+    for damage in &recovered.damage {
+        match damage {
+            Damage::Signature => fixes.push("rewrote the png signature".to_string()),
+            Damage::Crc { chunk_type, .. } if converted.is_none() => {
+                fixes.push(format!("recomputed the CRC of {}: {}", chunk_type, damage))
+            }
+            Damage::MissingIend => {}
+            _ => unrepaired.push(damage.to_string()),
+        }
+    }
+    if recovered.png.ensure_iend()? {
+        fixes.push("appended IEND".to_string());
+    }
+    Ok(Repaired {
+        png: recovered.png,
+        fixes,
+        unrepaired,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use std::str::FromStr;
+
+    // 数据中含有 \r\n、单独的 \r 和单独的 \n 的文件
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"line\r\nbreaks\r here\n and\r\n\n there".to_vec(),
+        ));
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_undo_lf_to_crlf() {
+        let bytes = testing_bytes();
+        let converted = replace(&bytes, b"\n", b"\r\n");
+        assert!(Png::try_from(converted.as_ref()).is_err());
+        let repaired = repair(&converted).unwrap();
+        assert_eq!(repaired.fixes, ["undid LF to CRLF conversion"]);
+        assert!(repaired.unrepaired.is_empty());
+        assert_eq!(repaired.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_undo_crlf_to_lf() {
+        let bytes = testing_bytes();
+        let converted = replace(&bytes, b"\r\n", b"\n");
+        let repaired = repair(&converted).unwrap();
+        assert_eq!(repaired.fixes, ["undid CRLF to LF conversion"]);
+        assert_eq!(repaired.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_undo_crlf_to_lf_in_large_idat() {
+        // 超过 64 KB 的 IDAT，其中有两处 \r\n 和很多单独的 \n
+        let mut image = testing_image(160, 160, 2, 8);
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for byte in image.data.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = (state >> 24) as u8;
+        }
+        let mut png = image.to_png().unwrap();
+        let mut idat = png.image_data();
+        assert!(idat.len() > 64 << 10);
+        idat[1000] = b'\r';
+        idat[1001] = b'\n';
+        idat[50000] = b'\r';
+        idat[50001] = b'\n';
+        assert_eq!(idat.windows(2).filter(|&x| x == b"\r\n").count(), 2);
+        assert!(idat.iter().filter(|&&x| x == b'\n').count() > 200);
+        png.set_image_chunks(vec![idat]).unwrap();
+        let bytes = png.as_bytes();
+
+        let converted = replace(&bytes, b"\r\n", b"\n");
+        let repaired = repair(&converted).unwrap();
+        assert_eq!(repaired.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_unlocatable_line_breaks() {
+        // 丢失的 \r 超过 MAX_LOST_CR 时放弃，不猜测
+        let mut png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"a\r\nb\r\nc\r\nd".to_vec(),
+        ));
+        let converted = replace(&png.as_bytes(), b"\r\n", b"\n");
+        let repaired = repair(&converted).unwrap();
+        assert!(repaired.fixes.is_empty());
+        assert!(!repaired.unrepaired.is_empty());
+        assert!(repaired.unrepaired[0].contains("ruSt chunk"));
+    }
+
+    #[test]
+    fn test_crc_after_newline_undo_is_not_recomputed() {
+        let mut bytes = testing_bytes();
+        let len = bytes.len();
+        bytes[len - 13] ^= 0x01;
+        let converted = replace(&bytes, b"\n", b"\r\n");
+        let repaired = repair(&converted).unwrap();
+        assert_eq!(repaired.unrepaired.len(), 1);
+        assert!(repaired.unrepaired[0].contains("CRC mismatch in ruSt"));
+    }
+
+    #[test]
+    fn test_recompute_crc_and_append_iend() {
+        let bytes = testing_bytes();
+        let len = bytes.len();
+        let mut damaged = bytes[..len - 12].to_vec();
+        damaged[len - 13] ^= 0xff;
+        let repaired = repair(&damaged).unwrap();
+        assert_eq!(repaired.fixes.len(), 2);
+        assert!(repaired.fixes[0].starts_with("recomputed the CRC of ruSt"));
+        assert_eq!(repaired.fixes[1], "appended IEND");
+        assert!(repaired.unrepaired.is_empty());
+        assert_eq!(repaired.png.as_bytes(), bytes);
+        assert!(Image::try_from(&repaired.png).is_ok());
+    }
+
+    #[test]
+    fn test_intact_file() {
+        let bytes = testing_bytes();
+        let repaired = repair(&bytes).unwrap();
+        assert!(repaired.fixes.is_empty());
+        assert_eq!(repaired.png.as_bytes(), bytes);
+    }
+}
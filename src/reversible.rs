@@ -0,0 +1,284 @@
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::stego::{bits_to_bytes, bytes_to_bits, KeyRng, PayloadReader, PAYLOAD_OVERHEAD};
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+
+/*
+直方图平移的可逆信息隐藏：
+- 取样本值直方图的峰值 P 和一个（尽量为空的）零点 Z，P 与 Z 之间的值整体向 Z 平移 1，空出 P 旁边的值
+- 值为 P 的样本携带比特：0 保持 P，1 变为 P 向 Z 方向的相邻值
+- 原来值为 Z 的样本会和平移后的值冲突，它们的位置记录在溢出表中，和消息一起嵌入
+前 16 个位置（按密钥打乱后）的最低位保存 P 和 Z，它们原来的最低位也放在载荷中，
+所以提取消息后可以逐位还原出原始图像。
+载荷：原最低位（2 字节） + 溢出表长度（4 字节） + 溢出表（每项 4 字节） + 消息块
+ */
+const SIDE_BITS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Shift {
+    peak: u8,
+    zero: u8,
+}
+
+impl Shift {
+    // 从直方图中选择峰值和零点
+    fn choose(histogram: &[usize; 256]) -> Shift {
+        let peak = (0..=255u8)
+            .max_by_key(|&v| (histogram[v as usize], std::cmp::Reverse(v)))
+            .unwrap_or(0);
+        // 零点与峰值相邻时，只有真正为空才不会和携带比特的值混淆
+        let zero = (0..=255u8)
+            .filter(|&v| v != peak && (histogram[v as usize] == 0 || v.abs_diff(peak) >= 2))
+            .min_by_key(|&v| (histogram[v as usize], v.abs_diff(peak)))
+            .unwrap_or(0);
+        Self { peak, zero }
+    }
+
+    fn step(&self) -> i16 {
+        if self.zero > self.peak {
+            1
+        } else {
+            -1
+        }
+    }
+
+    // 嵌入后表示比特 1 的值
+    fn marked(&self) -> u8 {
+        (self.peak as i16 + self.step()) as u8
+    }
+
+    // 严格位于峰值和零点之间、需要平移的值
+    fn between(&self, value: i16) -> bool {
+        let (low, high) = if self.peak < self.zero {
+            (self.peak, self.zero)
+        } else {
+            (self.zero, self.peak)
+        };
+        value > low as i16 && value < high as i16
+    }
+}
+
+fn keyed_positions(image: &Image, key: &str) -> Result<Vec<usize>> {
+    let mut positions = image.lsb_positions()?;
+    if positions.len() <= SIDE_BITS {
+        bail!("Image is too small");
+    }
+    KeyRng::new(key).shuffle(&mut positions);
+    Ok(positions)
+}
+
+fn side_bits(image: &Image, side: &[usize]) -> Vec<u8> {
+    side.iter().map(|&pos| image.data[pos] & 1).collect()
+}
+
+// 选择峰值和零点，返回可携带的比特数和溢出表
+fn plan(image: &Image, body: &[usize]) -> (Shift, usize, Vec<u32>) {
+    let mut histogram = [0; 256];
+    for &pos in body {
+        histogram[image.data[pos] as usize] += 1;
+    }
+    let shift = Shift::choose(&histogram);
+    let overflow = (0..body.len())
+        .filter(|&i| image.data[body[i]] == shift.zero)
+        .map(|i| i as u32)
+        .collect();
+    (shift, histogram[shift.peak as usize], overflow)
+}
+
+// 可嵌入的消息字节数（已扣除原最低位、溢出表和块的长度、类型、CRC）
+pub(crate) fn capacity(image: &Image, key: &str) -> Result<usize> {
+    let positions = keyed_positions(image, key)?;
+    let (_, available, overflow) = plan(image, &positions[SIDE_BITS..]);
+    Ok((available / 8).saturating_sub(2 + 4 + overflow.len() * 4 + PAYLOAD_OVERHEAD))
+}
+
+// 嵌入消息，返回被修改的样本数
+pub(crate) fn embed(image: &mut Image, key: &str, chunk: &Chunk) -> Result<usize> {
+    let positions = keyed_positions(image, key)?;
+    let (side, body) = positions.split_at(SIDE_BITS);
+    let (shift, available, overflow) = plan(image, body);
+
+    let mut payload = bits_to_bytes(&side_bits(image, side));
+    payload.extend((overflow.len() as u32).to_be_bytes());
+    payload.extend(overflow.iter().flat_map(|x| x.to_be_bytes()));
+    payload.extend(chunk.as_bytes());
+    let bits = bytes_to_bits(&payload);
+    if bits.len() > available {
+        bail!(
+            "Message too large: needs {} samples, only {} available",
+            bits.len(),
+            available
+        );
+    }
+
+    let mut changed = 0;
+    let mut bits = bits.into_iter();
+    for &pos in body {
+        let value = image.data[pos];
+        if shift.between(value as i16) {
+            image.data[pos] = (value as i16 + shift.step()) as u8;
+            changed += 1;
+        } else if value == shift.peak && bits.next() == Some(1) {
+            image.data[pos] = shift.marked();
+            changed += 1;
+        }
+    }
+    for (&pos, bit) in side.iter().zip(bytes_to_bits(&[shift.peak, shift.zero])) {
+        if image.data[pos] & 1 != bit {
+            image.data[pos] ^= 1;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+struct Payload {
+    side_bits: Vec<u8>,
+    overflow: HashSet<usize>,
+    chunk: Chunk,
+}
+
+fn read_payload(image: &Image, shift: Shift, body: &[usize]) -> Result<Payload> {
+    let bits: Vec<u8> = body
+        .iter()
+        .filter_map(|&pos| match image.data[pos] {
+            x if x == shift.peak => Some(0),
+            x if x == shift.marked() => Some(1),
+            _ => None,
+        })
+        .collect();
+    let not_found = || "No embedded message found (wrong key?)";
+    let header = bits.get(..SIDE_BITS + 32).with_context(not_found)?;
+    let count = u32::from_be_bytes(bits_to_bytes(&header[SIDE_BITS..]).as_slice().try_into()?);
+    let start = SIDE_BITS + 32;
+    let end = start + count as usize * 32;
+    let overflow = bits_to_bytes(bits.get(start..end).with_context(not_found)?)
+        .chunks_exact(4)
+        .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]) as usize)
+        .collect();
+    let chunk = PayloadReader::default()
+        .push(bits[end..].iter().copied())?
+        .with_context(not_found)?;
+    Ok(Payload {
+        side_bits: header[..SIDE_BITS].to_vec(),
+        overflow,
+        chunk,
+    })
+}
+
+fn read_shift(image: &Image, side: &[usize]) -> Result<Shift> {
+    let bytes = bits_to_bytes(&side_bits(image, side));
+    let shift = Shift {
+        peak: bytes[0],
+        zero: bytes[1],
+    };
+    if shift.peak == shift.zero {
+        bail!("No embedded message found (wrong key?)");
+    }
+    Ok(shift)
+}
+
+pub(crate) fn extract(image: &Image, key: &str) -> Result<Chunk> {
+    let positions = keyed_positions(image, key)?;
+    let (side, body) = positions.split_at(SIDE_BITS);
+    let shift = read_shift(image, side)?;
+    Ok(read_payload(image, shift, body)?.chunk)
+}
+
+// 提取消息并把图像还原为嵌入前的样子
+pub(crate) fn restore(image: &mut Image, key: &str) -> Result<Chunk> {
+    let positions = keyed_positions(image, key)?;
+    let (side, body) = positions.split_at(SIDE_BITS);
+    let shift = read_shift(image, side)?;
+    let payload = read_payload(image, shift, body)?;
+
+    for (i, &pos) in body.iter().enumerate() {
+        let value = image.data[pos];
+        let original = value as i16 - shift.step();
+        if value == shift.marked() {
+            image.data[pos] = shift.peak;
+        } else if shift.between(original) && !(value == shift.zero && payload.overflow.contains(&i))
+        {
+            image.data[pos] = original as u8;
+        }
+    }
+    for (&pos, &bit) in side.iter().zip(&payload.side_bits) {
+        image.data[pos] = (image.data[pos] & !1) | bit;
+    }
+    Ok(payload.chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    fn testing_chunk(message: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    // 大部分样本集中在几个值上，像自然图像一样有明显的峰值
+    fn smooth_image(full_range: bool) -> Image {
+        let mut image = testing_image(64, 64, 0, 8);
+        for (i, value) in image.data.iter_mut().enumerate() {
+            *value = match i {
+                // 每个值都出现，直方图中没有空位，需要溢出表
+                _ if full_range && i < 256 => i as u8,
+                _ => 120 + (i % 5) as u8,
+            };
+        }
+        image
+    }
+
+    #[test]
+    fn test_reversible_restore() {
+        let original = smooth_image(false);
+        let mut image = original.clone();
+        let changed = embed(&mut image, "key", &testing_chunk("annotation")).unwrap();
+        assert!(changed > 0);
+        assert_eq!(
+            extract(&image, "key").unwrap().data_as_string().unwrap(),
+            "annotation"
+        );
+        let chunk = restore(&mut image, "key").unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "annotation");
+        assert_eq!(image.data, original.data);
+    }
+
+    #[test]
+    fn test_reversible_overflow_map() {
+        let original = smooth_image(true);
+        let mut image = original.clone();
+        embed(&mut image, "key", &testing_chunk("full histogram")).unwrap();
+        let chunk = restore(&mut image, "key").unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "full histogram");
+        assert_eq!(image.data, original.data);
+    }
+
+    #[test]
+    fn test_reversible_message_too_large() {
+        let image = smooth_image(true);
+        let capacity = capacity(&image, "key").unwrap();
+        let fits = testing_chunk(&"x".repeat(capacity));
+        assert!(embed(&mut image.clone(), "key", &fits).is_ok());
+        let too_large = testing_chunk(&"x".repeat(capacity + 1));
+        assert!(embed(&mut image.clone(), "key", &too_large).is_err());
+
+        let mut image = smooth_image(false);
+        let message = "x".repeat(1000);
+        assert!(embed(&mut image, "key", &testing_chunk(&message)).is_err());
+    }
+
+    #[test]
+    fn test_reversible_wrong_key() {
+        let mut image = smooth_image(false);
+        embed(&mut image, "key", &testing_chunk("annotation")).unwrap();
+        assert!(extract(&image, "other key").is_err());
+    }
+}
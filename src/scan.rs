@@ -0,0 +1,339 @@
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::png::Png;
+use anyhow::{bail, Context, Error, Result};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/*
+类似 zsteg 的暴力提取：对每个比特平面和通道组合，按行（xy）或按列（yx）扫描像素，
+依次取出每个样本的指定比特，再按高位在前（msb）或低位在前（lsb）拼成字节。
+提取结果按开头的魔数和可打印字符的比例标记，便于发现隐藏的文件和文本。
+名称与 zsteg 类似，例如 b0,rgb,msb,xy 表示 R、G、B 样本的最低位，按行扫描，高位在前。
+ */
+const PREVIEW: usize = 64;
+const TEXT_RATIO: f64 = 0.9;
+// 平滑区域的比特平面常常是重复的字符（例如 UUUU），不算作文本
+const TEXT_DISTINCT: usize = 8;
+
+// 各颜色类型可以扫描的通道组合，索引色图像扫描调色板索引
+fn channel_sets(color_type: u8) -> &'static [(&'static str, &'static [usize])] {
+    match color_type {
+        0 => &[("gray", &[0])],
+        2 => &[("r", &[0]), ("g", &[1]), ("b", &[2]), ("rgb", &[0, 1, 2])],
+        3 => &[("index", &[0])],
+        4 => &[("gray", &[0]), ("a", &[1]), ("ga", &[0, 1])],
+        _ => &[
+            ("r", &[0]),
+            ("g", &[1]),
+            ("b", &[2]),
+            ("a", &[3]),
+            ("rgb", &[0, 1, 2]),
+            ("rgba", &[0, 1, 2, 3]),
+        ],
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Spec {
+    // 比特平面，0 为最低位
+    plane: u8,
+    channels: String,
+    msb: bool,
+    columns: bool,
+}
+
+impl FromStr for Spec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').collect();
+        let [plane, channels, order, scan] = parts[..] else {
+            bail!("Expected a spec like b0,rgb,msb,xy");
+        };
+        let plane = plane
+            .strip_prefix('b')
+            .and_then(|x| x.parse().ok())
+            .with_context(|| format!("Invalid bit plane: {}", plane))?;
+        let msb = match order {
+            "msb" => true,
+            "lsb" => false,
+            _ => bail!("Bit order must be msb or lsb"),
+        };
+        let columns = match scan {
+            "xy" => false,
+            "yx" => true,
+            _ => bail!("Scan order must be xy or yx"),
+        };
+        Ok(Self {
+            plane,
+            channels: channels.to_string(),
+            msb,
+            columns,
+        })
+    }
+}
+
+impl Display for Spec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "b{},{},{},{}",
+            self.plane,
+            self.channels,
+            if self.msb { "msb" } else { "lsb" },
+            if self.columns { "yx" } else { "xy" }
+        )
+    }
+}
+
+pub(crate) struct Candidate {
+    pub(crate) spec: Spec,
+    // 按魔数识别出的类型
+    pub(crate) kind: Option<&'static str>,
+    // 开头 PREVIEW 个字节中可打印字符的比例
+    pub(crate) printable: f64,
+    pub(crate) preview: Vec<u8>,
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<18} {:<6} {:>5.2}  {}",
+            self.spec.to_string(),
+            self.kind.unwrap_or("-"),
+            self.printable,
+            self.preview.escape_ascii()
+        )
+    }
+}
+
+// 图像上所有可以尝试的组合
+pub(crate) fn specs(image: &Image) -> Vec<Spec> {
+    let mut specs = Vec::new();
+    for columns in [false, true] {
+        for &(channels, _) in channel_sets(image.ihdr.color_type) {
+            for plane in 0..image.ihdr.bit_depth {
+                for msb in [true, false] {
+                    specs.push(Spec {
+                        plane,
+                        channels: channels.to_string(),
+                        msb,
+                        columns,
+                    });
+                }
+            }
+        }
+    }
+    specs
+}
+
+pub(crate) fn extract(image: &Image, spec: &Spec) -> Result<Vec<u8>> {
+    let channels = channel_sets(image.ihdr.color_type)
+        .iter()
+        .find(|(name, _)| *name == spec.channels)
+        .map(|(_, channels)| *channels)
+        .with_context(|| format!("Channels {} are not available in this image", spec.channels))?;
+    if spec.plane >= image.ihdr.bit_depth {
+        bail!(
+            "Bit plane {} is out of range for bit depth {}",
+            spec.plane,
+            image.ihdr.bit_depth
+        );
+    }
+    let width = image.ihdr.width as usize;
+    let height = image.ihdr.height as usize;
+    let pixels = (0..width * height).map(|i| {
+        if spec.columns {
+            i % height * width + i / height
+        } else {
+            i
+        }
+    });
+
+    let mut bytes = Vec::with_capacity(width * height * channels.len() / 8);
+    let (mut byte, mut count) = (0u8, 0);
+    for pixel in pixels {
+        for &channel in channels {
+            let bit = (image.sample(pixel, channel) >> spec.plane) as u8 & 1;
+            byte = if spec.msb {
+                (byte << 1) | bit
+            } else {
+                byte | (bit << count)
+            };
+            count += 1;
+            if count == 8 {
+                bytes.push(byte);
+                (byte, count) = (0, 0);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+fn classify(data: &[u8], printable: f64) -> Option<&'static str> {
+    const MAGICS: [(&[u8], &str); 5] = [
+        (b"PK\x03\x04", "zip"),
+        (b"PK\x05\x06", "zip"),
+        (b"\x1f\x8b", "gzip"),
+        (&Png::STANDARD_HEADER, "png"),
+        (b"\x7fELF", "elf"),
+    ];
+    if let Some((_, kind)) = MAGICS.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(kind);
+    }
+    // 本工具的像素方式写入的消息：长度 + 块类型 + 数据 + CRC
+    let length = data
+        .get(..4)
+        .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]) as usize);
+    if let Some(end) = length.and_then(|x| x.checked_add(12)) {
+        if data.len() >= end && Chunk::try_from(&data[..end]).is_ok() {
+            return Some("pngme");
+        }
+    }
+    let mut distinct = data[..data.len().min(PREVIEW)].to_vec();
+    distinct.sort();
+    distinct.dedup();
+    (data.len() >= PREVIEW && printable >= TEXT_RATIO && distinct.len() >= TEXT_DISTINCT)
+        .then_some("text")
+}
+
+fn printable_ratio(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let printable = data
+        .iter()
+        .filter(|&&x| x.is_ascii_graphic() || matches!(x, b' ' | b'\t' | b'\n' | b'\r'))
+        .count();
+    printable as f64 / data.len() as f64
+}
+
+pub(crate) fn scan(image: &Image) -> Result<Vec<Candidate>> {
+    specs(image)
+        .into_iter()
+        .map(|spec| {
+            let data = extract(image, &spec)?;
+            let preview = data[..data.len().min(PREVIEW)].to_vec();
+            let printable = printable_ratio(&preview);
+            Ok(Candidate {
+                kind: classify(&data, printable),
+                spec,
+                printable,
+                preview,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::stego::bytes_to_bits;
+
+    // 按给定的组合把数据写入图像，与 extract 的顺序相同
+    fn hide(image: &mut Image, spec: &Spec, data: &[u8]) {
+        let channels = channel_sets(image.ihdr.color_type)
+            .iter()
+            .find(|(name, _)| *name == spec.channels)
+            .unwrap()
+            .1;
+        let (width, height) = (image.ihdr.width as usize, image.ihdr.height as usize);
+        let mut bits = data.iter().flat_map(|&byte| {
+            let bits = bytes_to_bits(&[byte]);
+            if spec.msb {
+                bits
+            } else {
+                bits.into_iter().rev().collect()
+            }
+        });
+        for i in 0..width * height {
+            let pixel = if spec.columns {
+                i % height * width + i / height
+            } else {
+                i
+            };
+            for &channel in channels {
+                let Some(bit) = bits.next() else { return };
+                let value = image.sample(pixel, channel) & !(1 << spec.plane);
+                image.set_sample(pixel, channel, value | ((bit as u16) << spec.plane));
+            }
+        }
+    }
+
+    #[test]
+    fn test_spec_round_trip() {
+        let spec = Spec::from_str("b3,rgb,lsb,yx").unwrap();
+        assert_eq!(spec.plane, 3);
+        assert!(!spec.msb);
+        assert!(spec.columns);
+        assert_eq!(spec.to_string(), "b3,rgb,lsb,yx");
+        assert!(Spec::from_str("b0,rgb,msb").is_err());
+        assert!(Spec::from_str("x0,rgb,msb,xy").is_err());
+    }
+
+    #[test]
+    fn test_finds_hidden_zip() {
+        let mut image = testing_image(32, 32, 6, 8);
+        let spec = Spec::from_str("b0,rgb,msb,xy").unwrap();
+        hide(&mut image, &spec, b"PK\x03\x04 hidden archive");
+        let candidates = scan(&image).unwrap();
+        let found = candidates.iter().find(|x| x.spec == spec).unwrap();
+        assert_eq!(found.kind, Some("zip"));
+        assert!(found.preview.starts_with(b"PK\x03\x04 hidden"));
+    }
+
+    #[test]
+    fn test_finds_text_in_columns() {
+        let mut image = testing_image(40, 40, 2, 8);
+        let spec = Spec::from_str("b1,g,lsb,yx").unwrap();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(4);
+        hide(&mut image, &spec, text.as_bytes());
+        let found = scan(&image)
+            .unwrap()
+            .into_iter()
+            .find(|x| x.spec == spec)
+            .unwrap();
+        assert_eq!(found.kind, Some("text"));
+        assert_eq!(found.printable, 1.0);
+    }
+
+    #[test]
+    fn test_repeated_characters_are_not_text() {
+        let mut image = testing_image(40, 40, 0, 8);
+        let spec = Spec::from_str("b0,gray,msb,xy").unwrap();
+        hide(&mut image, &spec, &[b'U'; 200]);
+        let found = scan(&image)
+            .unwrap()
+            .into_iter()
+            .find(|x| x.spec == spec)
+            .unwrap();
+        assert_eq!(found.printable, 1.0);
+        assert_eq!(found.kind, None);
+    }
+
+    #[test]
+    fn test_finds_pngme_payload_in_palette_indices() {
+        let mut image = testing_image(32, 32, 3, 4);
+        let spec = Spec::from_str("b0,index,msb,xy").unwrap();
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"indices".to_vec());
+        hide(&mut image, &spec, &chunk.as_bytes());
+        assert_eq!(extract(&image, &spec).unwrap()[..19], chunk.as_bytes());
+        let found = scan(&image)
+            .unwrap()
+            .into_iter()
+            .find(|x| x.spec == spec)
+            .unwrap();
+        assert_eq!(found.kind, Some("pngme"));
+    }
+
+    #[test]
+    fn test_unavailable_channels() {
+        let image = testing_image(8, 8, 0, 8);
+        assert!(extract(&image, &Spec::from_str("b0,rgb,msb,xy").unwrap()).is_err());
+        assert!(extract(&image, &Spec::from_str("b8,gray,msb,xy").unwrap()).is_err());
+    }
+}
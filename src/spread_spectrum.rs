@@ -0,0 +1,247 @@
+use crate::image::Image;
+use crate::palette::Palette;
+use crate::stats::chi_square_tail;
+use crate::stego::KeyRng;
+use anyhow::{bail, Context, Result};
+
+/*
+扩频鲁棒水印：在亮度上叠加一个由密钥生成的 32x32 伪随机 ±1 图案，整幅图像平铺。
+图案中每个位置对应 64 位 ID 中的一位，比特为 1 时加上图案，为 0 时减去图案。
+检测时先用高通滤波去掉图像内容，把残差按 32x32 折叠，再尝试所有平移量（适应裁剪），
+相关性最强的平移量给出 ID，它相对其他平移量的显著程度给出置信度。
+像素值整体保留，所以删除辅助块、重新压缩不影响检测；小幅裁剪和转换为调色板图像也能检测到。
+ */
+const TILE: usize = 32;
+const ID_BITS: usize = 64;
+
+struct Pattern {
+    // 图案中每个位置对应的 ID 比特序号
+    bits: Vec<usize>,
+    signs: Vec<f64>,
+}
+
+impl Pattern {
+    fn new(key: &str) -> Pattern {
+        let mut rng = KeyRng::new(key);
+        let mut bits: Vec<usize> = (0..TILE * TILE).map(|i| i % ID_BITS).collect();
+        rng.shuffle(&mut bits);
+        let signs = (0..TILE * TILE)
+            .map(|_| if rng.next_u64() & 1 == 1 { 1.0 } else { -1.0 })
+            .collect();
+        Self { bits, signs }
+    }
+}
+
+pub(crate) struct Detection {
+    pub(crate) id: u64,
+    // 检测结果不是偶然出现的概率
+    pub(crate) confidence: f64,
+}
+
+// 嵌入 ID，strength 为亮度的变化量（按 8 位计算），返回被修改的样本数
+pub(crate) fn embed(image: &mut Image, key: &str, id: u64, strength: u8) -> Result<usize> {
+    if image.ihdr.color_type == 3 || image.ihdr.bit_depth < 8 {
+        bail!("Robust marks require 8 or 16 bit grayscale or truecolor images");
+    }
+    let pattern = Pattern::new(key);
+    let width = image.ihdr.width as usize;
+    let pixels = width * image.ihdr.height as usize;
+    let color_channels = image.ihdr.channels() - image.ihdr.has_alpha() as usize;
+    let max = image.max_sample() as i32;
+    let scale = max / 255;
+
+    let mut changed = 0;
+    for pixel in 0..pixels {
+        let cell = pixel / width % TILE * TILE + pixel % width % TILE;
+        let bit = (id >> (ID_BITS - 1 - pattern.bits[cell])) & 1;
+        let direction = if bit == 1 { 1 } else { -1 };
+        let delta = strength as i32 * scale * pattern.signs[cell] as i32 * direction;
+        for channel in 0..color_channels {
+            let value = image.sample(pixel, channel) as i32;
+            let marked = (value + delta).clamp(0, max);
+            if marked != value {
+                image.set_sample(pixel, channel, marked as u16);
+                changed += 1;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+// 每个像素按 8 位计算的亮度
+fn luminance(image: &Image, palette: Option<&Palette>) -> Result<Vec<f64>> {
+    let ihdr = image.ihdr;
+    let pixels = ihdr.width as usize * ihdr.height as usize;
+    let scale = 255.0 / image.max_sample() as f64;
+    let luma = |[r, g, b]: [f64; 3]| 0.299 * r + 0.587 * g + 0.114 * b;
+    (0..pixels)
+        .map(|pixel| {
+            let sample = |channel| image.sample(pixel, channel) as f64 * scale;
+            Ok(match ihdr.color_type {
+                3 => {
+                    let palette = palette.with_context(|| "No PLTE chunk")?;
+                    luma(palette.rgb(image.sample(pixel, 0) as u8).map(|x| x as f64))
+                }
+                2 | 6 => luma([sample(0), sample(1), sample(2)]),
+                _ => sample(0),
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn detect(image: &Image, palette: Option<&Palette>, key: &str) -> Result<Detection> {
+    let width = image.ihdr.width as usize;
+    let height = image.ihdr.height as usize;
+    let luminance = luminance(image, palette)?;
+
+    // 高通滤波：减去上下左右邻居的平均值，再按图案大小折叠
+    let mut folded = vec![0.0; TILE * TILE];
+    for y in 0..height {
+        for x in 0..width {
+            let neighbors: Vec<f64> = [
+                (x > 0).then(|| luminance[y * width + x - 1]),
+                (x + 1 < width).then(|| luminance[y * width + x + 1]),
+                (y > 0).then(|| luminance[(y - 1) * width + x]),
+                (y + 1 < height).then(|| luminance[(y + 1) * width + x]),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            if neighbors.is_empty() {
+                continue;
+            }
+            let mean = neighbors.iter().sum::<f64>() / neighbors.len() as f64;
+            folded[y % TILE * TILE + x % TILE] += luminance[y * width + x] - mean;
+        }
+    }
+
+    /*
+    尝试所有平移量。没有水印时每个比特的相关值近似服从均值为 0 的正态分布，
+    除以它的标准差后平方求和，得到自由度为 64 的卡方统计量。
+     */
+    let pattern = Pattern::new(key);
+    let mut best = (0.0, [0.0; ID_BITS]);
+    for dy in 0..TILE {
+        for dx in 0..TILE {
+            let mut correlations = [0.0; ID_BITS];
+            let mut energies = [0.0; ID_BITS];
+            for v in 0..TILE {
+                for u in 0..TILE {
+                    let cell = v * TILE + u;
+                    let value = folded[(v + TILE - dy) % TILE * TILE + (u + TILE - dx) % TILE];
+                    correlations[pattern.bits[cell]] += pattern.signs[cell] * value;
+                    energies[pattern.bits[cell]] += value * value;
+                }
+            }
+            let statistic = correlations
+                .iter()
+                .zip(energies)
+                .filter(|(_, energy)| *energy > 0.0)
+                .map(|(correlation, energy)| correlation * correlation / energy)
+                .sum::<f64>();
+            if statistic > best.0 {
+                best = (statistic, correlations);
+            }
+        }
+    }
+
+    // 所有平移量中的最大值偶然达到这个程度的概率
+    let chance = chi_square_tail(best.0, ID_BITS as f64) * (TILE * TILE) as f64;
+    let confidence = 1.0 - chance.min(1.0);
+    let id = best
+        .1
+        .iter()
+        .fold(0, |acc, &x| (acc << 1) | (x > 0.0) as u64);
+    Ok(Detection { id, confidence })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::png::Png;
+    use std::str::FromStr;
+
+    const ID: u64 = 0x0123_4567_89ab_cdef;
+
+    // 平滑的灰色渐变加上少量纹理，R = G = B
+    fn natural_image() -> Image {
+        let mut image = testing_image(128, 128, 2, 8);
+        let mut rng = KeyRng::new("texture");
+        for pixel in 0..128 * 128 {
+            let (x, y) = (pixel % 128, pixel / 128);
+            let value = (40 + x + y / 2 + rng.below(5)) as u8;
+            image.data[pixel * 3..pixel * 3 + 3].fill(value);
+        }
+        image
+    }
+
+    fn crop(image: &Image, left: usize, top: usize) -> Image {
+        let mut cropped = testing_image(
+            image.ihdr.width - left as u32,
+            image.ihdr.height - top as u32,
+            2,
+            8,
+        );
+        let stride = image.ihdr.stride();
+        cropped.data = (top..image.ihdr.height as usize)
+            .flat_map(|y| image.data[y * stride + left * 3..(y + 1) * stride].to_vec())
+            .collect();
+        cropped
+    }
+
+    #[test]
+    fn test_detect_marked_image() {
+        let mut image = natural_image();
+        embed(&mut image, "key", ID, 2).unwrap();
+        let detection = detect(&image, None, "key").unwrap();
+        assert_eq!(detection.id, ID);
+        assert!(detection.confidence > 0.99);
+    }
+
+    #[test]
+    fn test_detect_after_crop_and_recompress() {
+        let mut image = natural_image();
+        embed(&mut image, "key", ID, 2).unwrap();
+        let png = crop(&image, 9, 5).to_png().unwrap();
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let image = Image::try_from(&png).unwrap();
+        let detection = detect(&image, None, "key").unwrap();
+        assert_eq!(detection.id, ID);
+        assert!(detection.confidence > 0.99);
+    }
+
+    #[test]
+    fn test_detect_after_palette_conversion() {
+        let mut image = natural_image();
+        embed(&mut image, "key", ID, 2).unwrap();
+        // 灰色图像最多只有 256 种颜色，转换为索引色
+        let mut colors: Vec<u8> = (0..128 * 128).map(|pixel| image.data[pixel * 3]).collect();
+        colors.sort();
+        colors.dedup();
+        let plte = Chunk::new(
+            ChunkType::from_str("PLTE").unwrap(),
+            colors.iter().flat_map(|&x| [x, x, x]).collect(),
+        );
+        let mut indexed = testing_image(128, 128, 3, 8);
+        for pixel in 0..128 * 128 {
+            indexed.data[pixel] = colors.binary_search(&image.data[pixel * 3]).unwrap() as u8;
+        }
+        let palette = Palette::try_from(&plte).unwrap();
+        let detection = detect(&indexed, Some(&palette), "key").unwrap();
+        assert_eq!(detection.id, ID);
+        assert!(detection.confidence > 0.99);
+    }
+
+    #[test]
+    fn test_detect_unmarked_image() {
+        let image = natural_image();
+        assert!(detect(&image, None, "key").unwrap().confidence < 0.5);
+
+        let mut image = natural_image();
+        embed(&mut image, "key", ID, 2).unwrap();
+        assert!(detect(&image, None, "other key").unwrap().confidence < 0.5);
+    }
+}
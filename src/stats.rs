@@ -0,0 +1,80 @@
+// 标准正态分布的上侧概率（Abramowitz-Stegun 7.1.26 近似）
+pub(crate) fn normal_tail(z: f64) -> f64 {
+    if z < 0.0 {
+        return 1.0 - normal_tail(-z);
+    }
+    let x = z / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    0.5 * poly * (-x * x).exp()
+}
+
+// 自由度为 k 的卡方分布的上侧概率（Wilson-Hilferty 近似）
+pub(crate) fn chi_square_tail(x: f64, k: f64) -> f64 {
+    let variance = 2.0 / (9.0 * k);
+    normal_tail(((x / k).cbrt() - (1.0 - variance)) / variance.sqrt())
+}
+
+// 一元二次方程 a x^2 + b x + c = 0 中绝对值较小的实根
+pub(crate) fn smaller_root(a: f64, b: f64, c: f64) -> Option<f64> {
+    if a.abs() < f64::EPSILON {
+        return (b.abs() >= f64::EPSILON).then(|| -c / b);
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let roots = [
+        (-b + discriminant.sqrt()) / (2.0 * a),
+        (-b - discriminant.sqrt()) / (2.0 * a),
+    ];
+    roots.into_iter().min_by(|x, y| x.abs().total_cmp(&y.abs()))
+}
+
+// 每个字节的香农熵（比特），范围 0 到 8
+pub(crate) fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &x in data {
+        counts[x as usize] += 1;
+    }
+    let total = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&x| x > 0)
+        .map(|&x| {
+            let p = x as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tails() {
+        assert!((normal_tail(0.0) - 0.5).abs() < 1e-6);
+        assert!((normal_tail(1.96) - 0.025).abs() < 1e-3);
+        assert!((normal_tail(-1.96) - 0.975).abs() < 1e-3);
+        // 自由度为 10 时 18.307 对应上侧概率 0.05
+        assert!((chi_square_tail(18.307, 10.0) - 0.05).abs() < 2e-3);
+    }
+
+    #[test]
+    fn test_smaller_root() {
+        assert_eq!(smaller_root(1.0, -3.0, 2.0), Some(1.0));
+        assert_eq!(smaller_root(0.0, 2.0, -1.0), Some(0.5));
+        assert_eq!(smaller_root(1.0, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(&[0, 1, 0, 1]), 1.0);
+        let all: Vec<u8> = (0..=255).collect();
+        assert!((entropy(&all) - 8.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::chunk::Chunk;
+use anyhow::{bail, Result};
+
+const CRC64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_ECMA_182);
+
+/*
+由密钥派生的伪随机数生成器（SplitMix64），用于打乱嵌入位置。
+只是为了让没有密钥的人无法按顺序读取，并不是加密算法。
+ */
+pub(crate) struct KeyRng(u64);
+
+impl KeyRng {
+    pub(crate) fn new(key: &str) -> KeyRng {
+        Self(CRC64.checksum(key.as_bytes()))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // [0, n) 范围内的随机数
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Fisher-Yates 洗牌
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+// 按高位在前的顺序展开为比特
+pub(crate) fn bytes_to_bits(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+        .collect()
+}
+
+pub(crate) fn bits_to_bytes(bits: &[u8]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|x| x.iter().fold(0, |byte, &bit| (byte << 1) | bit))
+        .collect()
+}
+
+/*
+非块方式嵌入的消息直接使用块的字节表示：长度(4) + 块类型(4) + 数据 + CRC(4)
+读出前 4 个字节即可知道整条消息的长度，CRC 可以用来判断密钥是否正确。
+ */
+pub(crate) const PAYLOAD_OVERHEAD: usize = 4 + 4 + 4;
+
+pub(crate) fn payload_len(length_bits: &[u8]) -> Result<usize> {
+    let length = u32::from_be_bytes(bits_to_bytes(&length_bits[..32]).as_slice().try_into()?);
+    Ok(length as usize + PAYLOAD_OVERHEAD)
+}
+
+// 逐步收集提取出的比特，读到完整的消息后返回
+#[derive(Default)]
+pub(crate) struct PayloadReader {
+    bits: Vec<u8>,
+    total_bits: Option<usize>,
+}
+
+impl PayloadReader {
+    pub(crate) fn push(&mut self, bits: impl IntoIterator<Item = u8>) -> Result<Option<Chunk>> {
+        self.bits.extend(bits);
+        if self.total_bits.is_none() && self.bits.len() >= 32 {
+            self.total_bits = Some(payload_len(&self.bits)? * 8);
+        }
+        match self.total_bits {
+            Some(total_bits) if self.bits.len() >= total_bits => {
+                payload_from_bits(&self.bits[..total_bits]).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+pub(crate) fn payload_from_bits(bits: &[u8]) -> Result<Chunk> {
+    match Chunk::try_from(bits_to_bytes(bits).as_ref()) {
+        Ok(chunk) => Ok(chunk),
+        Err(_) => bail!("No embedded message found (wrong key?)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_round_trip() {
+        let bytes = b"RuSt".to_vec();
+        let bits = bytes_to_bits(&bytes);
+        assert_eq!(&bits[..8], &[0, 1, 0, 1, 0, 0, 1, 0]);
+        assert_eq!(bits_to_bytes(&bits), bytes);
+    }
+
+    #[test]
+    fn test_key_rng_is_deterministic() {
+        let mut a: Vec<usize> = (0..100).collect();
+        let mut b = a.clone();
+        KeyRng::new("secret").shuffle(&mut a);
+        KeyRng::new("secret").shuffle(&mut b);
+        assert_eq!(a, b);
+
+        let mut c: Vec<usize> = (0..100).collect();
+        KeyRng::new("other").shuffle(&mut c);
+        assert_ne!(a, c);
+    }
+}
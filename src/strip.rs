@@ -0,0 +1,284 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use anyhow::Result;
+use std::fmt::{Display, Formatter};
+
+/*
+发布前删除显示图像不需要的块：
+- 关键块（IHDR、PLTE、IDAT、IEND 以及未知的关键块）总是保留，解码器必须理解它们
+- tRNS、pHYs（像素宽高比）和 APNG 动画块会改变显示的内容，也总是保留
+- 色彩管理块只有在明确要求时（critical 配置）才删除
+- 浏览器会按 eXIf 中的方向旋转或翻转图像，保留色彩管理块时 eXIf 被改写为只含方向（Orientation）一项，
+  没有方向时删除；允许列表中有 eXIf 时原样保留
+- 其余辅助块只保留允许列表中的类型，IEND 之后的数据总是删除
+辅助块中复制不安全（安全复制位为 0）的块依赖于图像数据，报告中会单独标出。
+ */
+const RENDERING: [&[u8; 4]; 5] = [b"tRNS", b"pHYs", b"acTL", b"fcTL", b"fdAT"];
+const COLOR: [&[u8; 4]; 8] = [
+    b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"cICP", b"sBIT", b"mDCV", b"cLLI",
+];
+const EXIF: &[u8; 4] = b"eXIf";
+const ORIENTATION: u16 = 0x0112;
+
+pub(crate) struct Stripped {
+    pub(crate) chunks: Vec<Chunk>,
+    pub(crate) trailer: usize,
+    // 改写 eXIf 时删除的字节数
+    pub(crate) exif: Option<usize>,
+}
+
+impl Display for Stripped {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Removed {} chunks", self.chunks.len())?;
+        for chunk in &self.chunks {
+            write!(
+                f,
+                "\n  {} ({} bytes{})",
+                chunk.chunk_type(),
+                chunk.data().len(),
+                if chunk.chunk_type().is_safe_to_copy() {
+                    ""
+                } else {
+                    ", unsafe to copy"
+                }
+            )?;
+        }
+        if let Some(removed) = self.exif {
+            write!(
+                f,
+                "\nRewrote eXIf to keep only the orientation, removed {} bytes",
+                removed
+            )?;
+        }
+        if self.trailer > 0 {
+            write!(f, "\nRemoved {} bytes after IEND", self.trailer)?;
+        }
+        Ok(())
+    }
+}
+
+// 只含方向一项的 eXIf（TIFF 结构，字节序与原来相同），原来没有方向时返回 None
+fn orientation_only(data: &[u8]) -> Option<Vec<u8>> {
+    let little = match data.get(..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let read16 = |x: usize| {
+        let bytes = data.get(x..x.checked_add(2)?)?;
+        let bytes = [bytes[0], bytes[1]];
+        Some(if little {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read32 = |x: usize| {
+        let bytes = data.get(x..x.checked_add(4)?)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Some(if little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let write16 = |x: u16| {
+        if little {
+            x.to_le_bytes()
+        } else {
+            x.to_be_bytes()
+        }
+    };
+    let write32 = |x: u32| {
+        if little {
+            x.to_le_bytes()
+        } else {
+            x.to_be_bytes()
+        }
+    };
+
+    // 方向在第一个 IFD 中，类型为 SHORT，个数为 1，值放在项内
+    let ifd = read32(4)? as usize;
+    for i in 0..read16(ifd)? as usize {
+        let entry = ifd.checked_add(2 + 12 * i)?;
+        if read16(entry)? != ORIENTATION || read16(entry + 2)? != 3 || read32(entry + 4)? != 1 {
+            continue;
+        }
+        let mut result = data[..4].to_vec();
+        result.extend_from_slice(&write32(8));
+        result.extend_from_slice(&write16(1));
+        result.extend_from_slice(&write16(ORIENTATION));
+        result.extend_from_slice(&write16(3));
+        result.extend_from_slice(&write32(1));
+        result.extend_from_slice(&write16(read16(entry + 8)?));
+        result.extend_from_slice(&[0, 0]);
+        // 没有下一个 IFD
+        result.extend_from_slice(&write32(0));
+        return Some(result);
+    }
+    None
+}
+
+// keep_color 为 false 时删除色彩管理块和 eXIf，allowlist 中的辅助块类型额外保留
+pub(crate) fn strip(png: &mut Png, keep_color: bool, allowlist: &[String]) -> Result<Stripped> {
+    let allowed = |bytes: &[u8; 4]| allowlist.iter().any(|x| x.as_bytes() == bytes);
+    let mut exif = None;
+    if keep_color && !allowed(EXIF) {
+        for chunk in png.chunks_mut() {
+            if &chunk.chunk_type().bytes() != EXIF {
+                continue;
+            }
+            if let Some(data) = orientation_only(chunk.data()) {
+                *exif.get_or_insert(0) += chunk.data().len() - data.len();
+                *chunk = Chunk::new(ChunkType::try_from(*EXIF)?, data);
+            }
+        }
+    }
+    let chunks = png.remove_chunks(|chunk| {
+        let chunk_type = chunk.chunk_type();
+        let bytes = chunk_type.bytes();
+        let keep = chunk_type.is_critical()
+            || RENDERING.contains(&&bytes)
+            || (keep_color && COLOR.contains(&&bytes))
+            || (exif.is_some() && &bytes == EXIF)
+            || allowed(&bytes);
+        !keep
+    });
+    let trailer = png.trailer().len();
+    png.set_trailer(Vec::new());
+    Ok(Stripped {
+        chunks,
+        trailer,
+        exif,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let mut png = testing_image(8, 8, 3, 4).to_png().unwrap();
+        for (chunk_type, data) in [
+            ("PLTE", vec![0; 48]),
+            ("tRNS", vec![0, 255]),
+            ("gAMA", vec![0, 0, 0xb1, 0x8f]),
+            ("iCCP", b"profile\0\0".to_vec()),
+            ("tEXt", b"Author\0someone".to_vec()),
+            ("tIME", vec![7, 234, 10, 18, 12, 0, 0]),
+            ("pHYs", vec![0, 0, 11, 19, 0, 0, 11, 19, 1]),
+            ("ruSt", b"secret".to_vec()),
+        ] {
+            png.append_chunk(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data));
+        }
+        png.set_trailer(b"trailing".to_vec());
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_keep_color() {
+        let mut png = testing_png();
+        let stripped = strip(&mut png, true, &[]).unwrap();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "IDAT", "PLTE", "tRNS", "gAMA", "iCCP", "pHYs", "IEND"]
+        );
+        assert_eq!(stripped.chunks.len(), 3);
+        assert_eq!(stripped.trailer, 8);
+        assert!(png.trailer().is_empty());
+        assert!(stripped
+            .to_string()
+            .contains("tIME (7 bytes, unsafe to copy)"));
+        assert!(stripped.to_string().contains("tEXt (14 bytes)\n"));
+    }
+
+    #[test]
+    fn test_critical_only() {
+        let mut png = testing_png();
+        let original = Image::try_from(&png).unwrap();
+        strip(&mut png, false, &[]).unwrap();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "IDAT", "PLTE", "tRNS", "pHYs", "IEND"]
+        );
+        assert_eq!(Image::try_from(&png).unwrap().data, original.data);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let mut png = testing_png();
+        strip(&mut png, true, &["tEXt".to_string()]).unwrap();
+        assert_eq!(
+            types(&png),
+            ["IHDR", "IDAT", "PLTE", "tRNS", "gAMA", "iCCP", "tEXt", "pHYs", "IEND"]
+        );
+    }
+
+    // 大端的 eXIf：相机型号（ASCII，放在 IFD 之后）和方向 6（顺时针旋转 90 度）
+    fn testing_exif() -> Vec<u8> {
+        let mut exif = b"MM\0*".to_vec();
+        exif.extend_from_slice(&8u32.to_be_bytes());
+        exif.extend_from_slice(&2u16.to_be_bytes());
+        for (tag, kind, count, value) in [(0x010f, 2, 8, 38u32), (ORIENTATION, 3, 1, 6 << 16)] {
+            exif.extend_from_slice(&u16::to_be_bytes(tag));
+            exif.extend_from_slice(&u16::to_be_bytes(kind));
+            exif.extend_from_slice(&u32::to_be_bytes(count));
+            exif.extend_from_slice(&value.to_be_bytes());
+        }
+        exif.extend_from_slice(&0u32.to_be_bytes());
+        exif.extend_from_slice(b"Camera1\0");
+        exif
+    }
+
+    #[test]
+    fn test_exif_orientation() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("eXIf").unwrap(),
+            testing_exif(),
+        ));
+        let stripped = strip(&mut png, true, &[]).unwrap();
+        let exif = png.chunk_by_type("eXIf").unwrap().data();
+        assert_eq!(exif.len(), 26);
+        assert_eq!(orientation_only(exif).unwrap(), exif);
+        assert_eq!(&exif[18..20], &[0, 6]);
+        assert!(!exif.windows(6).any(|x| x == b"Camera"));
+        assert_eq!(stripped.exif, Some(46 - 26));
+        assert!(stripped.to_string().contains("Rewrote eXIf"));
+
+        // 没有方向时删除
+        let mut png = testing_png();
+        let mut exif = testing_exif();
+        exif[9] = 1;
+        png.append_chunk(Chunk::new(ChunkType::from_str("eXIf").unwrap(), exif));
+        strip(&mut png, true, &[]).unwrap();
+        assert!(png.chunk_by_type("eXIf").is_none());
+
+        // 明确删除色彩管理块时 eXIf 也被删除，允许列表中的 eXIf 原样保留
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("eXIf").unwrap(),
+            testing_exif(),
+        ));
+        strip(&mut png, false, &[]).unwrap();
+        assert!(png.chunk_by_type("eXIf").is_none());
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("eXIf").unwrap(),
+            testing_exif(),
+        ));
+        strip(&mut png, true, &["eXIf".to_string()]).unwrap();
+        assert_eq!(png.chunk_by_type("eXIf").unwrap().data(), testing_exif());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use anyhow::{Context, Result};
+
+/*
+将消息以块的字节表示追加到 IEND 之后。
+IEND 之后已经有数据（例如 ZIP）时追加在最后，不改变原有数据的偏移。
+解码时在尾部数据中查找类型匹配且 CRC 正确的块，有多个时取最后一个。
+ */
+pub(crate) fn embed(png: &mut Png, chunk: &Chunk) {
+    let trailer = [png.trailer(), &chunk.as_bytes()].concat();
+    png.set_trailer(trailer);
+}
+
+pub(crate) fn extract(png: &Png, chunk_type: &ChunkType) -> Result<Chunk> {
+    let trailer = png.trailer();
+    (0..trailer.len().saturating_sub(11))
+        .rev()
+        .filter(|&i| trailer[i + 4..i + 8] == chunk_type.bytes())
+        .find_map(|i| {
+            let length = u32::from_be_bytes(trailer[i..i + 4].try_into().ok()?) as usize;
+            let end = i.checked_add(12)?.checked_add(length)?;
+            Chunk::try_from(trailer.get(i..end)?).ok()
+        })
+        .with_context(|| "No such chunk in trailer")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_trailer_round_trip() {
+        let mut png = testing_image(4, 4, 2, 8).to_png().unwrap();
+        png.set_trailer(b"existing data".to_vec());
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        embed(&mut png, &Chunk::new(chunk_type, b"after IEND".to_vec()));
+
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert!(png.trailer().starts_with(b"existing data"));
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let actual = extract(&png, &chunk_type).unwrap();
+        assert_eq!(actual.data_as_string().unwrap(), "after IEND");
+
+        let other = ChunkType::from_str("RuSt").unwrap();
+        assert!(extract(&png, &other).is_err());
+    }
+}
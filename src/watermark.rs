@@ -0,0 +1,227 @@
+use crate::image::{Ihdr, Image};
+use crate::stego::bytes_to_bits;
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+
+/*
+脆弱水印：把图像分成 8x8 的块，对每个块计算 HMAC-SHA256（不包括最低位），截取前 64 位，
+循环写入该块所有的最低位。任何修改（包括任意一个最低位）都会让所在块对不上，从而定位被篡改的区域。
+没有密钥无法为修改后的块算出正确的最低位。
+MAC 中还包括整个 IHDR（宽、高、位深、颜色类型、隔行方式）和块的位置，从尺寸或格式不同的图像中
+复制同一位置的块也会被发现。尺寸和格式都相同的图像之间无法区分，同一个密钥不应当用于这样的多张图像。
+ */
+const BLOCK_SIZE: usize = 8;
+const HASH_BITS: usize = 64;
+
+struct Blocks {
+    columns: usize,
+    rows: usize,
+    // 每个块中可以修改最低位的位置
+    positions: Vec<Vec<usize>>,
+    // 所有最低位清零后的像素数据
+    masked: Vec<u8>,
+}
+
+impl Blocks {
+    fn new(image: &Image) -> Result<Blocks> {
+        let width = image.ihdr.width as usize;
+        let height = image.ihdr.height as usize;
+        let columns = width.div_ceil(BLOCK_SIZE);
+        let rows = height.div_ceil(BLOCK_SIZE);
+        let bytes_per_pixel = image.ihdr.bytes_per_pixel();
+        let mut positions = vec![Vec::new(); columns * rows];
+        let mut masked = image.data.clone();
+        for pos in image.lsb_positions()? {
+            let pixel = pos / bytes_per_pixel;
+            let (x, y) = (pixel % width, pixel / width);
+            positions[y / BLOCK_SIZE * columns + x / BLOCK_SIZE].push(pos);
+            masked[pos] &= !1;
+        }
+        Ok(Self {
+            columns,
+            rows,
+            positions,
+            masked,
+        })
+    }
+
+    // 块中每个最低位位置应有的比特，64 位的 MAC 循环重复
+    fn hash_bits(&self, ihdr: &Ihdr, key: &str, block: usize) -> Result<Vec<u8>> {
+        let width = ihdr.width as usize;
+        let bytes_per_pixel = ihdr.bytes_per_pixel();
+        let (bx, by) = (block % self.columns, block / self.columns);
+        let x_end = ((bx + 1) * BLOCK_SIZE).min(width);
+        let y_end = ((by + 1) * BLOCK_SIZE).min(ihdr.height as usize);
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(|e| anyhow!("{}", e))?;
+        mac.update(&ihdr.width.to_be_bytes());
+        mac.update(&ihdr.height.to_be_bytes());
+        mac.update(&[ihdr.bit_depth, ihdr.color_type, ihdr.interlace]);
+        mac.update(&(bx as u32).to_be_bytes());
+        mac.update(&(by as u32).to_be_bytes());
+        for y in by * BLOCK_SIZE..y_end {
+            let start = (y * width + bx * BLOCK_SIZE) * bytes_per_pixel;
+            let end = (y * width + x_end) * bytes_per_pixel;
+            mac.update(&self.masked[start..end]);
+        }
+        let bits = bytes_to_bits(&mac.finalize().into_bytes()[..HASH_BITS / 8]);
+        Ok((0..self.positions[block].len())
+            .map(|i| bits[i % HASH_BITS])
+            .collect())
+    }
+}
+
+// 被修改的块
+pub(crate) struct TamperMap {
+    columns: usize,
+    rows: usize,
+    modified: Vec<bool>,
+}
+
+impl TamperMap {
+    pub(crate) fn modified_blocks(&self) -> usize {
+        self.modified.iter().filter(|&&x| x).count()
+    }
+
+    // 与原图同样大小的灰度掩码，被修改的块为白色
+    pub(crate) fn mask(&self, ihdr: &Ihdr) -> Image {
+        let width = ihdr.width as usize;
+        let height = ihdr.height as usize;
+        let data = (0..width * height)
+            .map(|pixel| {
+                let (x, y) = (pixel % width, pixel / width);
+                match self.modified[y / BLOCK_SIZE * self.columns + x / BLOCK_SIZE] {
+                    true => 255,
+                    false => 0,
+                }
+            })
+            .collect();
+        Image {
+            ihdr: Ihdr {
+                width: ihdr.width,
+                height: ihdr.height,
+                bit_depth: 8,
+                color_type: 0,
+                interlace: 0,
+            },
+            data,
+            filters: vec![0; height],
+        }
+    }
+}
+
+impl Display for TamperMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for row in self.modified.chunks(self.columns) {
+            let line: String = row.iter().map(|&x| if x { 'X' } else { '.' }).collect();
+            writeln!(f, "{}", line)?;
+        }
+        write!(
+            f,
+            "{} of {} blocks modified",
+            self.modified_blocks(),
+            self.columns * self.rows
+        )
+    }
+}
+
+// 嵌入水印，返回被修改的样本数
+pub(crate) fn embed(image: &mut Image, key: &str) -> Result<usize> {
+    let blocks = Blocks::new(image)?;
+    let mut changed = 0;
+    for (block, positions) in blocks.positions.iter().enumerate() {
+        let bits = blocks.hash_bits(&image.ihdr, key, block)?;
+        for (&pos, bit) in positions.iter().zip(bits) {
+            if image.data[pos] & 1 != bit {
+                image.data[pos] ^= 1;
+                changed += 1;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+pub(crate) fn verify(image: &Image, key: &str) -> Result<TamperMap> {
+    let blocks = Blocks::new(image)?;
+    let modified = blocks
+        .positions
+        .iter()
+        .enumerate()
+        .map(|(block, positions)| {
+            let bits = blocks.hash_bits(&image.ihdr, key, block)?;
+            Ok(positions
+                .iter()
+                .zip(bits)
+                .any(|(&pos, bit)| image.data[pos] & 1 != bit))
+        })
+        .collect::<Result<_>>()?;
+    Ok(TamperMap {
+        columns: blocks.columns,
+        rows: blocks.rows,
+        modified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::testing_image;
+
+    #[test]
+    fn test_watermark_intact() {
+        let mut image = testing_image(30, 20, 2, 8);
+        embed(&mut image, "key").unwrap();
+        let map = verify(&image, "key").unwrap();
+        assert_eq!(map.modified_blocks(), 0);
+        assert_eq!(map.to_string(), "....\n....\n....\n0 of 12 blocks modified");
+    }
+
+    #[test]
+    fn test_watermark_localises_tampering() {
+        let mut image = testing_image(30, 20, 6, 8);
+        embed(&mut image, "key").unwrap();
+        // 修改 (12, 17) 处像素的 alpha，位于第 1 列第 2 行的块
+        image.data[(17 * 30 + 12) * 4 + 3] ^= 0x80;
+        let map = verify(&image, "key").unwrap();
+        assert_eq!(map.to_string(), "....\n....\n.X..\n1 of 12 blocks modified");
+
+        let mask = map.mask(&image.ihdr);
+        assert_eq!(mask.data[17 * 30 + 12], 255);
+        assert_eq!(mask.data[0], 0);
+    }
+
+    #[test]
+    fn test_watermark_covers_every_lsb() {
+        // RGB 的 8x8 块有 192 个最低位，超过 MAC 的 64 位
+        let mut image = testing_image(8, 8, 2, 8);
+        embed(&mut image, "key").unwrap();
+        image.data[(7 * 8 + 7) * 3] ^= 1;
+        assert_eq!(verify(&image, "key").unwrap().modified_blocks(), 1);
+    }
+
+    #[test]
+    fn test_watermark_collage() {
+        // 从另一张同样密钥、不同尺寸的图像复制左上角的块
+        let mut image = testing_image(16, 16, 2, 8);
+        embed(&mut image, "key").unwrap();
+        let mut other = testing_image(24, 16, 2, 8);
+        embed(&mut other, "key").unwrap();
+        for y in 0..BLOCK_SIZE {
+            let row = BLOCK_SIZE * 3;
+            image.data[y * 16 * 3..y * 16 * 3 + row]
+                .copy_from_slice(&other.data[y * 24 * 3..y * 24 * 3 + row]);
+        }
+        let map = verify(&image, "key").unwrap();
+        assert_eq!(map.to_string(), "X.\n..\n1 of 4 blocks modified");
+    }
+
+    #[test]
+    fn test_watermark_wrong_key() {
+        let mut image = testing_image(16, 16, 0, 16);
+        embed(&mut image, "key").unwrap();
+        assert_eq!(verify(&image, "other key").unwrap().modified_blocks(), 4);
+    }
+}
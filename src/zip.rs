@@ -0,0 +1,175 @@
+use anyhow::{bail, Context, Result};
+
+/*
+ZIP 文件从末尾的目录结束记录（EOCD）开始读取：
+EOCD：签名(4) + 磁盘编号(2) + 目录所在磁盘(2) + 本磁盘条目数(2) + 条目总数(2) + 目录大小(4) + 目录偏移(4) + 注释长度(2)
+中央目录中每一项的第 42 个字节开始是对应本地文件头的偏移(4)。
+这些偏移都是相对文件开头的，所以把 ZIP 追加到 PNG 之后，需要把它们加上 PNG 部分的长度。
+ */
+const EOCD_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+const CENTRAL_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x01, 0x02];
+const LOCAL_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+const EOCD_LEN: usize = 22;
+const CENTRAL_LEN: usize = 46;
+
+fn u16_at(bytes: &[u8], pos: usize) -> Result<u16> {
+    let field = bytes.get(pos..pos + 2).with_context(|| "Truncated zip")?;
+    Ok(u16::from_le_bytes(field.try_into()?))
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> Result<u32> {
+    let field = bytes.get(pos..pos + 4).with_context(|| "Truncated zip")?;
+    Ok(u32::from_le_bytes(field.try_into()?))
+}
+
+fn set_u32_at(bytes: &mut [u8], pos: usize, value: u32) {
+    bytes[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[derive(Debug)]
+pub(crate) struct Archive {
+    eocd: usize,
+    entries: usize,
+    central_offset: usize,
+    // 中央目录中每一项的位置
+    central_entries: Vec<usize>,
+}
+
+impl Archive {
+    // 解析 ZIP，base 为 ZIP 数据在文件中的起始位置（偏移相对于它计算）
+    pub(crate) fn parse(bytes: &[u8], base: usize) -> Result<Archive> {
+        if bytes.len() < EOCD_LEN {
+            bail!("Too short to be a zip");
+        }
+        // EOCD 之后最多还有 65535 字节的注释
+        let eocd = (0..=bytes.len().saturating_sub(EOCD_LEN))
+            .rev()
+            .take(u16::MAX as usize + 1)
+            .find(|&i| bytes[i..i + 4] == EOCD_SIGNATURE)
+            .with_context(|| "No end of central directory record")?;
+        let entries = u16_at(bytes, eocd + 10)? as usize;
+        let central_size = u32_at(bytes, eocd + 12)? as usize;
+        let central_offset = u32_at(bytes, eocd + 16)?;
+        if central_offset == u32::MAX || entries == u16::MAX as usize {
+            bail!("Zip64 archives are not supported");
+        }
+        let central_offset = (central_offset as usize)
+            .checked_sub(base)
+            .with_context(|| "Central directory offset out of range")?;
+        if central_offset + central_size > eocd {
+            bail!("Central directory offset out of range");
+        }
+
+        let mut central_entries = Vec::with_capacity(entries);
+        let mut pos = central_offset;
+        for _ in 0..entries {
+            if bytes.get(pos..pos + 4) != Some(&CENTRAL_SIGNATURE) {
+                bail!("Invalid central directory entry");
+            }
+            let local = (u32_at(bytes, pos + 42)? as usize)
+                .checked_sub(base)
+                .with_context(|| "Local header offset out of range")?;
+            if bytes.get(local..local + 4) != Some(&LOCAL_SIGNATURE) {
+                bail!("Invalid local file header");
+            }
+            central_entries.push(pos);
+            pos += CENTRAL_LEN
+                + u16_at(bytes, pos + 28)? as usize
+                + u16_at(bytes, pos + 30)? as usize
+                + u16_at(bytes, pos + 32)? as usize;
+        }
+        Ok(Self {
+            eocd,
+            entries,
+            central_offset,
+            central_entries,
+        })
+    }
+
+    pub(crate) fn entries(&self) -> usize {
+        self.entries
+    }
+}
+
+// 返回偏移全部加上 shift 之后的 ZIP 数据
+pub(crate) fn relocate(zip: &[u8], shift: usize) -> Result<Vec<u8>> {
+    let archive = Archive::parse(zip, 0)?;
+    let mut bytes = zip.to_vec();
+    let shifted = |offset: u32| -> Result<u32> {
+        u32::try_from(offset as usize + shift).with_context(|| "Zip offsets exceed 4 GiB")
+    };
+    for &pos in &archive.central_entries {
+        let offset = shifted(u32_at(&bytes, pos + 42)?)?;
+        set_u32_at(&mut bytes, pos + 42, offset);
+    }
+    let central_offset = shifted(archive.central_offset as u32)?;
+    set_u32_at(&mut bytes, archive.eocd + 16, central_offset);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // 只包含一个未压缩文件的 ZIP
+    pub(crate) fn testing_zip(name: &str, content: &[u8]) -> Vec<u8> {
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(content);
+        let mut local = LOCAL_SIGNATURE.to_vec();
+        local.extend([20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        local.extend(crc.to_le_bytes());
+        local.extend((content.len() as u32).to_le_bytes());
+        local.extend((content.len() as u32).to_le_bytes());
+        local.extend((name.len() as u16).to_le_bytes());
+        local.extend([0, 0]);
+        local.extend(name.as_bytes());
+        local.extend(content);
+
+        let mut central = CENTRAL_SIGNATURE.to_vec();
+        central.extend([20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        central.extend(crc.to_le_bytes());
+        central.extend((content.len() as u32).to_le_bytes());
+        central.extend((content.len() as u32).to_le_bytes());
+        central.extend((name.len() as u16).to_le_bytes());
+        central.extend([0; 12]);
+        central.extend(0u32.to_le_bytes());
+        central.extend(name.as_bytes());
+
+        let mut eocd = EOCD_SIGNATURE.to_vec();
+        eocd.extend([0, 0, 0, 0, 1, 0, 1, 0]);
+        eocd.extend((central.len() as u32).to_le_bytes());
+        eocd.extend((local.len() as u32).to_le_bytes());
+        eocd.extend([0, 0]);
+        [local, central, eocd].concat()
+    }
+
+    #[test]
+    fn test_parse_zip() {
+        let zip = testing_zip("a.txt", b"hello");
+        let archive = Archive::parse(&zip, 0).unwrap();
+        assert_eq!(archive.entries(), 1);
+        assert!(Archive::parse(b"not a zip", 0).is_err());
+    }
+
+    #[test]
+    fn test_short_trailer() {
+        // 比结束记录短的任何尾部数据，包括以结束记录签名开头的
+        for len in 0..EOCD_LEN {
+            assert!(Archive::parse(&vec![b'\n'; len], 0).is_err());
+            let mut trailer = b"PK\x05\x06".to_vec();
+            trailer.resize(len, 0);
+            assert!(Archive::parse(&trailer, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn test_relocate_zip() {
+        let zip = testing_zip("a.txt", b"hello");
+        let prefix = vec![0; 100];
+        let file = [prefix, relocate(&zip, 100).unwrap()].concat();
+        assert!(Archive::parse(&file, 0).is_ok());
+        // 没有修正偏移时，相对整个文件解析会失败
+        let file = [vec![0; 100], zip].concat();
+        assert!(Archive::parse(&file, 0).is_err());
+        assert!(Archive::parse(&file[100..], 0).is_ok());
+    }
+}
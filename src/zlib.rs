@@ -0,0 +1,232 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::stego::{bytes_to_bits, PayloadReader};
+use anyhow::{bail, Result};
+
+/*
+在 deflate 数据流的开头插入不产生任何输出的空块：
+- 空的存储块（BTYPE = 00，LEN = 0）表示比特 0
+- 空的固定哈夫曼块（BTYPE = 01，只有块结束符）表示比特 1
+最后再加一个空的存储块，它会对齐到字节边界，原来的 deflate 数据可以原样接在后面。
+解压后的数据完全相同，Adler-32 校验和也不需要改变，所有符合规范的解码器都能正常解码。
+ */
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    // deflate 按从低位到高位的顺序写入比特
+    fn write(&mut self, value: u32, count: usize) {
+        for i in 0..count {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if let Some(byte) = self.bytes.last_mut() {
+                *byte |= (((value >> i) & 1) as u8) << (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+
+    fn align(&mut self) {
+        self.bits = self.bytes.len() * 8;
+    }
+
+    // 空的存储块：BFINAL = 0，BTYPE = 00，对齐后 LEN = 0，NLEN = 0xFFFF
+    fn empty_stored_block(&mut self) {
+        self.write(0, 3);
+        self.align();
+        self.write(0, 16);
+        self.write(0xFFFF, 16);
+    }
+
+    // 空的固定哈夫曼块：BFINAL = 0，BTYPE = 01，块结束符 256 的编码为 7 个 0
+    fn empty_fixed_block(&mut self) {
+        self.write(0b010, 3);
+        self.write(0, 7);
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bits: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.bytes.get(self.bits / 8)?;
+            value |= (((byte >> (self.bits % 8)) & 1) as u32) << i;
+            self.bits += 1;
+        }
+        Some(value)
+    }
+
+    fn align(&mut self) {
+        self.bits = self.bits.div_ceil(8) * 8;
+    }
+
+    // 读取开头的一个空块，返回它表示的比特；不是空块时返回 None
+    fn empty_block(&mut self) -> Option<u8> {
+        match self.read(3)? {
+            0b000 => {
+                self.align();
+                let len = self.read(16)?;
+                let nlen = self.read(16)?;
+                (len == 0 && nlen == 0xFFFF).then_some(0)
+            }
+            0b010 => (self.read(7)? == 0).then_some(1),
+            _ => None,
+        }
+    }
+}
+
+// 拆分出 zlib 头（2 个字节）和 deflate 数据（不含末尾 4 个字节的 Adler-32）
+fn split_stream(stream: &[u8]) -> Result<(&[u8], &[u8], &[u8])> {
+    if stream.len() < 6 {
+        bail!("Image data is too short");
+    }
+    if stream[1] & 0x20 != 0 {
+        bail!("Zlib streams with a preset dictionary are not supported");
+    }
+    let (header, rest) = stream.split_at(2);
+    let (deflate, adler) = rest.split_at(rest.len() - 4);
+    Ok((header, deflate, adler))
+}
+
+// 去掉之前嵌入的空块，返回从字节边界开始的原始 deflate 数据
+fn strip_empty_blocks(deflate: &[u8]) -> &[u8] {
+    let mut reader = BitReader {
+        bytes: deflate,
+        bits: 0,
+    };
+    let mut start = 0;
+    while let Some(bit) = reader.empty_block() {
+        // 只有存储块结束时一定在字节边界上
+        if bit == 0 {
+            start = reader.bits / 8;
+        }
+    }
+    &deflate[start..]
+}
+
+pub(crate) fn embed(png: &mut Png, chunk: &Chunk) -> Result<()> {
+    let stream = png.image_data();
+    let (header, deflate, adler) = split_stream(&stream)?;
+
+    let mut writer = BitWriter::default();
+    for bit in bytes_to_bits(&chunk.as_bytes()) {
+        match bit {
+            0 => writer.empty_stored_block(),
+            _ => writer.empty_fixed_block(),
+        }
+    }
+    writer.empty_stored_block();
+
+    let new_stream = [header, &writer.bytes, strip_empty_blocks(deflate), adler].concat();
+
+    // 保持 IDAT 块的数量不变，多出来的数据放在最后一个 IDAT 块中
+    let lengths: Vec<usize> = png
+        .chunks()
+        .iter()
+        .filter(|x| x.chunk_type().bytes() == Png::IDAT)
+        .map(|x| x.length() as usize)
+        .collect();
+    let mut parts = Vec::with_capacity(lengths.len());
+    let mut rest = &new_stream[..];
+    for &length in &lengths[..lengths.len() - 1] {
+        let (part, remain) = rest.split_at(length.min(rest.len()));
+        parts.push(part.to_vec());
+        rest = remain;
+    }
+    parts.push(rest.to_vec());
+    png.set_image_chunks(parts)
+}
+
+pub(crate) fn extract(png: &Png) -> Result<Chunk> {
+    let stream = png.image_data();
+    let (_, deflate, _) = split_stream(&stream)?;
+    let mut reader = BitReader {
+        bytes: deflate,
+        bits: 0,
+    };
+    let mut payload = PayloadReader::default();
+    while let Some(bit) = reader.empty_block() {
+        if let Some(chunk) = payload.push([bit])? {
+            return Ok(chunk);
+        }
+    }
+    bail!("No embedded message found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::tests::testing_image;
+    use crate::image::Image;
+    use std::str::FromStr;
+
+    fn testing_chunk(message: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_zlib_round_trip() {
+        let image = testing_image(16, 16, 2, 8);
+        let mut png = image.to_png().unwrap();
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(&png.image_data()).unwrap();
+        embed(&mut png, &testing_chunk("deflate")).unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        // 解压后的扫描线完全相同
+        let actual = miniz_oxide::inflate::decompress_to_vec_zlib(&png.image_data()).unwrap();
+        assert_eq!(actual, raw);
+        assert_eq!(Image::try_from(&png).unwrap().data, image.data);
+        assert_eq!(extract(&png).unwrap().data_as_string().unwrap(), "deflate");
+    }
+
+    #[test]
+    fn test_zlib_keeps_idat_count() {
+        let image = testing_image(16, 16, 2, 8);
+        let mut png = image.to_png().unwrap();
+        let stream = png.image_data();
+        let (first, second) = stream.split_at(stream.len() / 2);
+        png.set_image_chunks(vec![first.to_vec(), second.to_vec()])
+            .unwrap();
+        embed(&mut png, &testing_chunk("two")).unwrap();
+
+        let idat_count = png
+            .chunks()
+            .iter()
+            .filter(|x| x.chunk_type().bytes() == Png::IDAT)
+            .count();
+        assert_eq!(idat_count, 2);
+        assert_eq!(extract(&png).unwrap().data_as_string().unwrap(), "two");
+    }
+
+    #[test]
+    fn test_zlib_embed_again_replaces_message() {
+        let image = testing_image(16, 16, 2, 8);
+        let mut png = image.to_png().unwrap();
+        embed(&mut png, &testing_chunk("first")).unwrap();
+        embed(&mut png, &testing_chunk("again")).unwrap();
+        assert_eq!(extract(&png).unwrap().data_as_string().unwrap(), "again");
+
+        let mut fresh = image.to_png().unwrap();
+        embed(&mut fresh, &testing_chunk("again")).unwrap();
+        assert_eq!(png.image_data(), fresh.image_data());
+    }
+
+    #[test]
+    fn test_zlib_nothing_embedded() {
+        let png = testing_image(16, 16, 2, 8).to_png().unwrap();
+        assert!(extract(&png).is_err());
+    }
+}